@@ -0,0 +1,99 @@
+/*
+ Not in the original program: plays the levels of the engine against each other
+*/
+
+//Run with: cargo run --release --example level_match [games] [first level] [last level]
+//Each level plays the next one. Every game starts with two random moves, and is played
+//twice with the colours swapped, so a level never wins only because it moved first. The
+//levels are given by name, by default from Beginner to Expert: Perfect does not use the
+//opening book, and its first moves take far too long.
+
+use std::env;
+use velena_rs::ai::{Engine, Level};
+use velena_rs::board::Board;
+
+const RANDOM_MOVES: usize = 2;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Outcome {
+    FirstWins,
+    Draw,
+    SecondWins
+}
+
+fn parse_level(name: &str) -> Level {
+    *Level::ALL.iter()
+        .find(|level| format!("{level:?}").eq_ignore_ascii_case(name))
+        .expect("Invalid level")
+}
+
+//The engines are given in the order they play
+fn play_game(opening: Board, mut engines: [&mut Engine; 2]) -> Outcome {
+    let mut board = opening;
+    let first_player = opening.player_to_play();
+    for engine in engines.iter_mut() {
+        engine.new_game();
+    }
+    let mut turn = 0;
+    loop {
+        let column = engines[turn].compute_move(board);
+        if board.is_move_winning(column, board.player_to_play()) {
+            return if board.player_to_play() == first_player {
+                Outcome::FirstWins
+            } else {
+                Outcome::SecondWins
+            };
+        }
+        board = board.make_move(column).expect("Invalid move");
+        if board.is_full() {
+            return Outcome::Draw;
+        }
+        turn = 1 - turn;
+    }
+}
+
+fn random_opening() -> Board {
+    let mut board = Board::new();
+    while board.filled_squares() < RANDOM_MOVES {
+        board = board.make_move(fastrand::usize(0..Board::WIDTH)).unwrap();
+    }
+    board
+}
+
+fn main() {
+    let games = env::args().nth(1).map_or(20, |games| games.parse().expect("Invalid number of games"));
+    let first = env::args().nth(2).map_or(Level::Beginner, |name| parse_level(&name));
+    let last = env::args().nth(3).map_or(Level::Expert, |name| parse_level(&name));
+    let levels: Vec<Level> = Level::ALL.into_iter().filter(|&level| level >= first && level <= last).collect();
+
+    for pair in levels.windows(2) {
+        let (weaker, stronger) = (pair[0], pair[1]);
+        let mut weaker_engine = Engine::new(weaker.options());
+        let mut stronger_engine = Engine::new(stronger.options());
+        let (mut wins, mut draws, mut losses) = (0, 0, 0);
+        let mut opening = Board::new();
+        for game in 0..games {
+            if game % 2 == 0 {
+                opening = random_opening();
+            }
+            //Even games: the stronger level moves first after the opening
+            let outcome = if game % 2 == 0 {
+                play_game(opening, [&mut stronger_engine, &mut weaker_engine])
+            } else {
+                match play_game(opening, [&mut weaker_engine, &mut stronger_engine]) {
+                    Outcome::FirstWins => Outcome::SecondWins,
+                    Outcome::Draw => Outcome::Draw,
+                    Outcome::SecondWins => Outcome::FirstWins
+                }
+            };
+            match outcome {
+                Outcome::FirstWins => wins += 1,
+                Outcome::Draw => draws += 1,
+                Outcome::SecondWins => losses += 1
+            }
+        }
+        let score = (2 * wins + draws) as f64 / (2 * games) as f64;
+        println!("{stronger:?} against {weaker:?}: {wins} wins, {draws} draws, {losses} losses, {:.0}% of the points",
+            100.0 * score);
+    }
+}
@@ -0,0 +1,55 @@
+/*
+ Not in the original program: measures the speed of the proof-number search
+*/
+
+//Run with: cargo run --release --example pn_throughput [node budget] [threads]
+//The middle game positions are decided within a few tens of thousands of nodes, so they
+//measure the searches which actually prove something: deep trees, long updates of the
+//ancestors, and many transpositions. The openings are never decided, they measure the
+//searches which run out of nodes.
+
+use std::env;
+use std::time::Instant;
+use velena_rs::board::Board;
+use velena_rs::heuristic::{self, Algorithm, Goal, SearchLimits};
+
+const MIDDLE_GAME: [&[usize]; 4] = [
+    &[5, 3, 3, 1, 4, 2, 2, 4, 3, 3, 5, 3], //Won
+    &[6, 1, 3, 4, 4, 3, 3, 3, 3, 2, 3, 1], //Won
+    &[1, 2, 6, 5, 3, 3, 1, 1, 2, 4, 1, 0], //Won
+    &[3, 5, 5, 6, 3, 3, 4, 2, 3, 4, 3, 3, 2, 4] //Lost
+];
+
+const OPENINGS: [&[usize]; 4] = [
+    &[],
+    &[3],
+    &[3, 3, 3, 3],
+    &[3, 2, 4, 3, 2, 4]
+];
+
+fn main() {
+    let nodes = env::args().nth(1).map_or(100000, |budget| budget.parse().expect("Invalid node budget"));
+    let threads = env::args().nth(2).map_or(1, |threads| threads.parse().expect("Invalid thread count"));
+    let limits = SearchLimits {
+        nodes,
+        threads,
+        ..SearchLimits::default()
+    };
+
+    for moves in MIDDLE_GAME.into_iter().chain(OPENINGS) {
+        let mut board = Board::new();
+        for &column in moves {
+            board = board.make_move(column).unwrap();
+        }
+
+        for algorithm in [Algorithm::ProofNumber, Algorithm::DepthFirst] {
+            for goal in [Goal::Win, Goal::AtLeastDraw] {
+                let start = Instant::now();
+                let result = heuristic::heuristic_search(board, goal, &limits, algorithm);
+                let seconds = start.elapsed().as_secs_f64();
+                println!("{:?} {:?} {:?}: {:?} with {} nodes in {:.2}s, {:.0} nodes/s",
+                         moves, algorithm, goal, result.verdict(), result.nodes(), seconds, result.nodes() as f64 / seconds);
+            }
+        }
+    }
+}
@@ -3,7 +3,12 @@
  */
 
 use crate::board::{Board, Player, Square};
-use crate::{book, heuristic};
+use crate::book;
+use crate::control::SearchControl;
+use crate::fallback;
+use crate::heuristic::{Algorithm, Goal, SearchLimits, SearchSession, Verdict};
+use crate::solver::{self, Score, Solver};
+use crate::table::TranspositionTable;
 
 fn random_winning_move(board: Board, player: Player) -> Option<usize> {
     let mut possible_moves = [0; Board::WIDTH];
@@ -105,11 +110,164 @@ fn black_best_move(board: Board) -> Option<usize> {
     }
 }
 
+//Deliberate mistakes of the weaker levels, only made when nothing was proved. The move is
+//then drawn among the ones the fallback search finds almost as good as the best one, so
+//the engine never gives away a win it can see.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Mistakes {
+    pub percent: u32, //Chances in a hundred of not playing the best move
+    pub margin: i32 //Largest loss of evaluation accepted, see analysis::evaluate
+}
+
+//Everything which changes the way the engine plays
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct EngineOptions {
+    pub opening_book: bool, //Also the opening moves of Black. Never used with the solver.
+    pub proof_number_search: bool,
+    pub goal: Goal,
+    pub limits: SearchLimits,
+    pub algorithm: Algorithm,
+    //Perfect play: the exact solver decides when the proof-number search did not. It may
+    //take a very long time early in the game, the control can stop it. It then gets a
+    //quarter of the memory limit, and the proof-number search the rest. The opening book and
+    //the answers to the first move of White are skipped, the solver cannot check them.
+    pub solver: bool,
+    pub fallback_depth: usize, //Of the alpha-beta search played when nothing was proved
+    pub mistakes: Option<Mistakes>
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        Level::Strong.options()
+    }
+}
+
+//Strength of the engine, from the weakest. Each level against the one below it, over 40
+//games of examples/level_match, the points being 2 for a win and 1 for a draw:
+//  Novice against Beginner          30 wins,  2 draws,  8 losses, 78% of the points
+//  Intermediate against Novice      30 wins,  4 draws,  6 losses, 80% of the points
+//  Strong against Intermediate      25 wins,  6 draws,  9 losses, 70% of the points
+//  Expert against Strong            25 wins,  5 draws, 10 losses, 69% of the points
+//Perfect is not measured: without the opening book, its first moves take far too long.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Level {
+    //Alpha-beta at depth 2, and a plausible mistake 40 times in a hundred
+    Beginner,
+    //Alpha-beta at depth 4, and a plausible mistake 20 times in a hundred
+    Novice,
+    //Proof-number search with the budget of the original program, without the opening
+    //book, then alpha-beta at depth 6 with a mistake 10 times in a hundred
+    Intermediate,
+    //The level 3 of the original program: opening book and proof-number search, then
+    //alpha-beta at depth 8
+    Strong,
+    //Opening book, proof-number search with 4 times the budget of the original program
+    //looking for a win then for a draw, then alpha-beta at depth 10
+    Expert,
+    //Proof-number search and the exact solver, from the first move of Black: none of the
+    //opening shortcuts of the other levels
+    Perfect
+}
+
+impl Level {
+    pub const ALL: [Level; 6] = [Level::Beginner, Level::Novice, Level::Intermediate, Level::Strong, Level::Expert, Level::Perfect];
+
+    //The levels a, b and c of the original program are 1, 2 and 3. Its levels 1 and 2
+    //played the same way, they are now the two levels below the original level 3.
+    pub fn from_legacy(level: usize) -> Self {
+        match level {
+            0 | 1 => Level::Novice,
+            2 => Level::Intermediate,
+            _ => Level::Strong
+        }
+    }
+
+    pub fn options(self) -> EngineOptions {
+        let strong = EngineOptions {
+            opening_book: true,
+            proof_number_search: true,
+            goal: Goal::default(),
+            limits: SearchLimits::default(),
+            algorithm: Algorithm::default(),
+            solver: false,
+            fallback_depth: fallback::DEFAULT_DEPTH,
+            mistakes: None
+        };
+        match self {
+            Level::Beginner => EngineOptions {
+                opening_book: false,
+                proof_number_search: false,
+                fallback_depth: 2,
+                mistakes: Some(Mistakes { percent: 40, margin: 60 }),
+                ..strong
+            },
+            Level::Novice => EngineOptions {
+                opening_book: false,
+                proof_number_search: false,
+                fallback_depth: 4,
+                mistakes: Some(Mistakes { percent: 20, margin: 30 }),
+                ..strong
+            },
+            Level::Intermediate => EngineOptions {
+                opening_book: false,
+                fallback_depth: 6,
+                mistakes: Some(Mistakes { percent: 10, margin: 15 }),
+                ..strong
+            },
+            Level::Strong => strong,
+            Level::Expert => EngineOptions {
+                goal: Goal::AvoidLoss,
+                limits: SearchLimits {
+                    nodes: 4 * SearchLimits::default().nodes,
+                    ..SearchLimits::default()
+                },
+                fallback_depth: 10,
+                ..strong
+            },
+            Level::Perfect => EngineOptions {
+                opening_book: false,
+                solver: true,
+                ..strong
+            }
+        }
+    }
+}
+
+//The level of the original program, see Level::from_legacy
 pub fn compute_ai_move(board: Board, level: usize) -> usize {
+    compute_ai_move_with_options(board, &Level::from_legacy(level).options())
+}
+
+pub fn compute_ai_move_with_options(board: Board, options: &EngineOptions) -> usize {
+    compute_ai_move_with_control(board, options, &mut SearchControl::default())
+}
+
+//The control applies to the proof-number search, the solver and the alpha-beta searches,
+//the other steps are immediate
+pub fn compute_ai_move_with_control(board: Board, options: &EngineOptions, control: &mut SearchControl) -> usize {
+    let mut session = SearchSession::for_one_search(options.goal, search_limits(options), options.algorithm);
+    choose_move(board, options, &mut session, &mut None, control)
+}
+
+//The limits of the proof-number search, without the memory of the solver
+fn search_limits(options: &EngineOptions) -> SearchLimits {
+    SearchLimits {
+        memory: options.limits.memory.map(|memory| memory - solver_memory(options).unwrap_or(0)),
+        ..options.limits
+    }
+}
+
+fn solver_memory(options: &EngineOptions) -> Option<usize> {
+    options.limits.memory.filter(|_| options.solver).map(|memory| memory / 4)
+}
+
+//The solver is only created when it is needed, its table is big
+fn choose_move(board: Board, options: &EngineOptions, session: &mut SearchSession, solver: &mut Option<Solver>,
+               control: &mut SearchControl) -> usize {
     if board.filled_squares() == 0 { //Board is empty
-        return 3; //Opening in the central column
+        return 3; //Opening in the central column, the only winning move according to Allis
     }
-    if board.filled_squares() == 1 { //We play after White
+    if board.filled_squares() == 1 && !options.solver { //We play after White
         return if board.get_square(1, 0).unwrap() == Square::Taken(Player::White) {
             2
         } else if board.get_square(5, 0).unwrap() == Square::Taken(Player::White) {
@@ -132,19 +290,194 @@ pub fn compute_ai_move(board: Board, level: usize) -> usize {
     if let Some(forced_move) = avoid_immediate_loss(board) { //We are forced to play
         return forced_move;
     }
-    //Special opening case for Black (I think)
-    if board.player_to_play() == Player::Black && (level >= 3 || board.filled_squares() == 1) {
-        if let Some(best_move) = black_best_move(board) { 
-            return best_move;
+    if options.opening_book && !options.solver {
+        //Special opening case for Black (I think)
+        if board.player_to_play() == Player::Black {
+            if let Some(best_move) = black_best_move(board) {
+                return best_move;
+            }
         }
-    }
-    if level >= 3 { //Let's look in the opening book
+        //Let's look in the opening book
         if let Some(opening) = book::use_opening_book(board) {
             return opening;
         }
     }
-    if let Some(heuristic) = heuristic::heuristic_best_play(board, false) {
-        return heuristic; //The heuristic was enough to find a solution
+    let mut losing_moves = Vec::new();
+    if options.proof_number_search {
+        let result = session.search_with_control(board, control);
+        if result.verdict() == Verdict::Proved {
+            return result.best_move().unwrap(); //The heuristic was enough to find a solution
+        }
+        losing_moves.extend_from_slice(result.losing_moves());
+    }
+    if options.solver {
+        let solver = solver.get_or_insert_with(|| solver_memory(options).map_or_else(Solver::new, Solver::with_memory));
+        if let Some(best_move) = solver_move(board, solver, control) {
+            return best_move;
+        }
+    }
+    if let Some(mistakes) = options.mistakes {
+        if fastrand::u32(0..100) < mistakes.percent {
+            let scores = fallback::move_scores(board, options.fallback_depth, options.limits.deadline, &control.token);
+            if let Some(mistake) = scores.and_then(|scores| plausible_mistake(scores, mistakes.margin, &losing_moves)) {
+                return mistake;
+            }
+        }
+    }
+    //The original program played in the first column here, even when it was full
+    fallback::fallback_move_excluding(board, options.fallback_depth, options.limits.deadline, &control.token, &losing_moves)
+        .expect("Invalid board! ")
+}
+
+//The fastest win, or the slowest loss. When the solver is cancelled, the move with the
+//best lower bound among the ones searched so far, unless it may lose. None otherwise.
+fn solver_move(board: Board, solver: &mut Solver, control: &mut SearchControl) -> Option<usize> {
+    let mut best: Option<(usize, Score)> = None;
+    for column in solver::COLUMN_ORDER {
+        let Ok(new_board) = board.make_move(column) else {
+            continue;
+        };
+        let bounds = solver.solve_with_control(new_board, control).parent();
+        if best.is_none_or(|(_, best_score)| bounds.lower() > best_score) {
+            best = Some((column, bounds.lower()));
+        }
+        if bounds.exact().is_none() {
+            return best.filter(|&(_, score)| score >= Score::Draw).map(|(column, _)| column);
+        }
+    }
+    best.map(|(column, _)| column)
+}
+
+//A move other than the best one, losing at most the margin and not among the moves proved to
+//lose. None when there is none.
+fn plausible_mistake(scores: [Option<i32>; Board::WIDTH], margin: i32, losing_moves: &[usize]) -> Option<usize> {
+    let best = scores.iter().flatten().copied().max()?;
+    let candidates: Vec<usize> = (0..Board::WIDTH)
+        .filter(|&column| scores[column].is_some_and(|score| score < best && score >= best - margin))
+        .filter(|column| !losing_moves.contains(column))
+        .collect();
+    if candidates.is_empty() {
+        None
+    } else {
+        Some(candidates[fastrand::usize(0..candidates.len())])
+    }
+}
+
+//Keeps the search session and the solver, and with them their transposition tables, from
+//one move to the next. The compute_ai_move functions start from nothing at each move.
+pub struct Engine {
+    options: EngineOptions,
+    session: SearchSession,
+    solver: Option<Solver>
+}
+
+impl Engine {
+    pub fn new(options: EngineOptions) -> Self {
+        Self {
+            options,
+            session: SearchSession::new(options.goal, search_limits(&options), options.algorithm),
+            solver: None
+        }
+    }
+
+    //For example to give the table another size or replacement policy
+    pub fn with_table(options: EngineOptions, table: TranspositionTable) -> Self {
+        Self {
+            options,
+            session: SearchSession::with_table(options.goal, search_limits(&options), options.algorithm, table),
+            solver: None
+        }
+    }
+
+    pub fn options(&self) -> &EngineOptions {
+        &self.options
+    }
+
+    pub fn table(&self) -> &TranspositionTable {
+        self.session.table()
     }
-    0
-}
\ No newline at end of file
+
+    //To clear it, resize it or change its replacement policy
+    pub fn table_mut(&mut self) -> &mut TranspositionTable {
+        self.session.table_mut()
+    }
+
+    //The trees of the previous game are dropped, but the tables are kept
+    pub fn new_game(&mut self) {
+        self.session.forget_trees();
+    }
+
+    pub fn compute_move(&mut self, board: Board) -> usize {
+        self.compute_move_with_control(board, &mut SearchControl::default())
+    }
+
+    pub fn compute_move_with_control(&mut self, board: Board, control: &mut SearchControl) -> usize {
+        choose_move(board, &self.options, &mut self.session, &mut self.solver, control)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::testing::play;
+
+    //Points of the first engine, 2 for a win and 1 for a draw, over a game started from the
+    //opening and a game with the colours swapped
+    fn match_points(opening: Board, first: &mut Engine, second: &mut Engine) -> u32 {
+        let mut points = 0;
+        for first_moves_first in [true, false] {
+            let mut board = opening;
+            let mut first_to_play = first_moves_first;
+            loop {
+                let engine = if first_to_play { &mut *first } else { &mut *second };
+                let column = engine.compute_move(board);
+                if board.is_move_winning(column, board.player_to_play()) {
+                    points += if first_to_play { 2 } else { 0 };
+                    break;
+                }
+                board = board.make_move(column).unwrap();
+                if board.is_full() {
+                    points += 1;
+                    break;
+                }
+                first_to_play = !first_to_play;
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn novice_beats_beginner() {
+        fastrand::seed(25);
+        let mut novice = Engine::new(Level::Novice.options());
+        let mut beginner = Engine::new(Level::Beginner.options());
+        let mut points = 0;
+        for opening in [[3, 3], [2, 4], [0, 6], [4, 1]] {
+            let opening = play(&opening);
+            points += match_points(opening, &mut novice, &mut beginner);
+        }
+        assert!(points > 8 + 4, "{points} points out of 16");
+    }
+
+    //The second search of the same position finds the values in the table of the solver
+    #[test]
+    fn solver_is_kept_and_sized() {
+        let options = EngineOptions {
+            opening_book: false,
+            proof_number_search: false,
+            limits: SearchLimits {
+                memory: Some(8 << 20),
+                ..SearchLimits::default()
+            },
+            ..Level::Perfect.options()
+        };
+        let mut engine = Engine::new(options);
+        let board = play(&[2, 2, 2, 3, 4, 5, 1, 1, 6, 1, 4, 2, 5, 4, 2, 6]);
+        let best_move = engine.compute_move(board);
+        let solver = engine.solver.as_ref().unwrap();
+        assert!(solver.memory() <= 2 << 20);
+        let first_search = solver.node_count();
+        assert_eq!(engine.compute_move(board), best_move);
+        assert!(engine.solver.as_ref().unwrap().node_count() - first_search < first_search / 2);
+    }
+}
@@ -0,0 +1,322 @@
+/*
+ evaluate.c
+*/
+
+//Threat analysis: a threat is an empty square completing a group of a player. Like in
+//Allis' thesis, rows are counted from 1, so odd threats are on rows 1, 3 and 5.
+
+use crate::board::{Board, Player, SquareSet};
+use crate::groups::{GroupSet, GROUP_MASKS};
+use crate::heuristic::{self, Algorithm, Goal, SearchLimits, Verdict};
+use crate::rules::EVEN_ROWS;
+use crate::solver::{Score, Solver};
+
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub struct Threats {
+    odd: SquareSet,
+    even: SquareSet
+}
+
+impl Threats {
+    fn from_mask(mask: u64) -> Self {
+        Self {
+            odd: SquareSet::from_mask(mask & !EVEN_ROWS),
+            even: SquareSet::from_mask(mask & EVEN_ROWS)
+        }
+    }
+
+    pub fn odd(self) -> SquareSet {
+        self.odd
+    }
+
+    pub fn even(self) -> SquareSet {
+        self.even
+    }
+
+    pub fn all(self) -> SquareSet {
+        SquareSet::from_mask(self.odd.mask() | self.even.mask())
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.odd.is_empty() && self.even.is_empty()
+    }
+
+    //Columns holding at least one odd threat
+    fn odd_columns(self) -> usize {
+        (0..Board::WIDTH).filter(|&column| self.odd.mask() & Board::column_mask(column) != 0).count()
+    }
+}
+
+//Every threat of the player, including the ones he could play right now
+pub fn threats(board: Board, player: Player) -> Threats {
+    Threats::from_mask(board.winning_positions(player))
+}
+
+//Threats with no other threat of either player below them in their column. The lowest
+//threat of a column decides what happens in it: the other ones can never be reached.
+pub fn useful_threats(board: Board, player: Player) -> Threats {
+    let all = board.winning_positions(Player::White) | board.winning_positions(Player::Black);
+    let mut lowest = 0;
+    for column in 0..Board::WIDTH {
+        let column_threats = all & Board::column_mask(column);
+        lowest |= column_threats & column_threats.wrapping_neg();
+    }
+    Threats::from_mask(board.winning_positions(player) & lowest)
+}
+
+//An approximation of Allis' rules, counting columns instead of following them: Black
+//controls zugzwang by default, since he gets every even square by following up. White takes
+//the control with useful odd threats, as long as he has them in more columns than Black
+//has useful odd threats of his own. Mixed odd and even threats, which Allis' rules settle
+//column by column, are ignored, so the guess can be wrong: strategy::find_strategy proves
+//the control of zugzwang.
+pub fn guess_zugzwang_controller(board: Board) -> Player {
+    let white = useful_threats(board, Player::White);
+    let black = useful_threats(board, Player::Black);
+    if white.odd_columns() > black.odd_columns() {
+        Player::White
+    } else {
+        Player::Black
+    }
+}
+
+//Weights of the static evaluation. A group is open for a player when the opponent has no
+//man in it, and it is worth more the more men of the player it holds. Odd threats are good
+//for White and even ones for Black, the other ones are worth less.
+const OPEN_GROUP_WEIGHTS: [i32; 4] = [0, 1, 4, 12];
+const GOOD_THREAT: i32 = 30;
+const BAD_THREAT: i32 = 10;
+const ZUGZWANG: i32 = 20;
+const CENTER_WEIGHTS: [i32; Board::WIDTH] = [0, 1, 2, 3, 2, 1, 0];
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct PlayerEvaluation {
+    open_groups: GroupSet,
+    open_groups_by_men: [usize; 4],
+    threats: Threats,
+    center: usize
+}
+
+impl PlayerEvaluation {
+    fn new(board: Board, player: Player) -> Self {
+        let own = board.player_positions(player);
+        let other = board.player_positions(player.opponent());
+        let mut open_groups = GroupSet::EMPTY;
+        let mut open_groups_by_men = [0; 4];
+        for (group, mask) in GROUP_MASKS.iter().enumerate() {
+            let men = (mask & own).count_ones() as usize;
+            //A complete group is not open anymore, the game is over
+            if mask & other == 0 && men < 4 {
+                open_groups = open_groups.union(GroupSet::single(group));
+                open_groups_by_men[men] += 1;
+            }
+        }
+        let center = (0..Board::WIDTH)
+            .map(|column| CENTER_WEIGHTS[column] as usize * (own & Board::column_mask(column)).count_ones() as usize)
+            .sum();
+        Self {
+            open_groups,
+            open_groups_by_men,
+            threats: useful_threats(board, player),
+            center
+        }
+    }
+
+    //Groups the player can still complete: the opponent has no man in them
+    pub fn open_groups(&self) -> GroupSet {
+        self.open_groups
+    }
+
+    //Number of open groups holding this many men of the player, from 0 to 3
+    pub fn open_groups_with(&self, men: usize) -> usize {
+        self.open_groups_by_men.get(men).copied().unwrap_or(0)
+    }
+
+    //Only the useful ones, see useful_threats
+    pub fn threats(&self) -> Threats {
+        self.threats
+    }
+
+    //Men of the player weighted by how close to the central column they are
+    pub fn center(&self) -> usize {
+        self.center
+    }
+
+    fn value(&self, player: Player) -> i32 {
+        let groups: i32 = (0..4).map(|men| OPEN_GROUP_WEIGHTS[men] * self.open_groups_by_men[men] as i32).sum();
+        let (good, bad) = match player {
+            Player::White => (self.threats.odd.len(), self.threats.even.len()),
+            Player::Black => (self.threats.even.len(), self.threats.odd.len())
+        };
+        groups + GOOD_THREAT * good as i32 + BAD_THREAT * bad as i32 + self.center as i32
+    }
+}
+
+//Static evaluation of a position the searches could not decide. It means nothing once the
+//game is over.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Evaluation {
+    white: PlayerEvaluation,
+    black: PlayerEvaluation,
+    guessed_zugzwang_controller: Player
+}
+
+impl Evaluation {
+    pub fn player(&self, player: Player) -> &PlayerEvaluation {
+        match player {
+            Player::White => &self.white,
+            Player::Black => &self.black
+        }
+    }
+
+    //See guess_zugzwang_controller
+    pub fn guessed_zugzwang_controller(&self) -> Player {
+        self.guessed_zugzwang_controller
+    }
+
+    //Positive when White is better
+    pub fn score(&self) -> i32 {
+        let zugzwang = match self.guessed_zugzwang_controller {
+            Player::White => ZUGZWANG,
+            Player::Black => -ZUGZWANG
+        };
+        self.white.value(Player::White) - self.black.value(Player::Black) + zugzwang
+    }
+
+    //Positive when the player is better
+    pub fn score_for(&self, player: Player) -> i32 {
+        match player {
+            Player::White => self.score(),
+            Player::Black => -self.score()
+        }
+    }
+}
+
+pub fn evaluate(board: Board) -> Evaluation {
+    Evaluation {
+        white: PlayerEvaluation::new(board, Player::White),
+        black: PlayerEvaluation::new(board, Player::Black),
+        guessed_zugzwang_controller: guess_zugzwang_controller(board)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Method {
+    Solver, //Exact, but may be very slow early in the game
+    ProofNumber(SearchLimits, Algorithm) //Quick, but often inconclusive
+}
+
+//Value of a move for the player who plays it
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ColumnValue {
+    Exact(Score),
+    //The proof-number search does not give the distances
+    Win,
+    AtLeastDraw,
+    Draw,
+    AtMostDraw,
+    Loss,
+    Unknown
+}
+
+//Value of the board for the player who just moved in it
+fn proof_number_value(board: Board, limits: &SearchLimits, algorithm: Algorithm) -> ColumnValue {
+    //The opponent is the root player of both searches
+    let opponent_wins = heuristic::heuristic_search(board, Goal::Win, limits, algorithm).verdict();
+    if opponent_wins == Verdict::Proved {
+        return ColumnValue::Loss;
+    }
+    let opponent_draws = heuristic::heuristic_search(board, Goal::AtLeastDraw, limits, algorithm).verdict();
+    match (opponent_wins, opponent_draws) {
+        (_, Verdict::Disproved) => ColumnValue::Win,
+        (Verdict::Disproved, Verdict::Proved) => ColumnValue::Draw,
+        (Verdict::Disproved, Verdict::Unknown) => ColumnValue::AtLeastDraw,
+        (_, Verdict::Proved) => ColumnValue::AtMostDraw,
+        _ => ColumnValue::Unknown
+    }
+}
+
+fn column_value(board: Board, column: usize, method: Method, solver: &mut Option<Solver>) -> Option<ColumnValue> {
+    let new_board = board.make_move(column).ok()?;
+    Some(match method {
+        Method::Solver => {
+            let solver = solver.get_or_insert_with(Solver::new);
+            ColumnValue::Exact(solver.solve(new_board).parent())
+        }
+        Method::ProofNumber(limits, algorithm) => if new_board.is_winning() {
+            ColumnValue::Win
+        } else if new_board.is_full() {
+            ColumnValue::Draw
+        } else {
+            proof_number_value(new_board, &limits, algorithm)
+        }
+    })
+}
+
+//Values of every move, None for the full columns. Like heuristic_best_play, we work on the
+//canonical orientation of the board, and mirrored columns are only searched once when the
+//board is symmetrical.
+pub fn analyse_columns(board: Board, method: Method) -> [Option<ColumnValue>; Board::WIDTH] {
+    let symmetric = board.symmetric_board();
+    let is_symmetric = symmetric < board;
+    let node_board = if is_symmetric {
+        symmetric
+    } else {
+        board
+    };
+    let self_symmetric = node_board.symmetric_board() == node_board;
+
+    let mut solver = None; //Shared between the columns, created on first use
+    let mut values = [None; Board::WIDTH];
+    for column in 0..Board::WIDTH {
+        let mirror = Board::WIDTH - 1 - column;
+        values[column] = if self_symmetric && mirror < column {
+            values[mirror]
+        } else {
+            column_value(node_board, column, method, &mut solver)
+        };
+    }
+
+    if is_symmetric {
+        values.reverse();
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::testing::play;
+
+    #[test]
+    fn evaluate_won_board() {
+        let board = play(&[0, 1, 0, 1, 0, 1, 0]);
+        let evaluation = evaluate(board);
+        assert_eq!(evaluation.player(Player::White).open_groups_with(4), 0);
+        let white = board.player_positions(Player::White);
+        assert!(evaluation.player(Player::White).open_groups().iter().all(|group| GROUP_MASKS[group] & white != GROUP_MASKS[group]));
+        //The score means nothing once the game is over, but it is computed and sees White ahead
+        assert!(evaluation.score() > 0);
+        assert_eq!(evaluation.score_for(Player::Black), -evaluation.score());
+    }
+
+    #[test]
+    fn guess_of_the_zugzwang_controller() {
+        assert_eq!(guess_zugzwang_controller(Board::new()), Player::Black);
+        //The even threat of Black above the odd one of White is useless
+        let board = play(&[0, 0, 1, 1, 2, 2]);
+        assert!(!threats(board, Player::Black).even().is_empty());
+        assert!(useful_threats(board, Player::Black).is_empty());
+        assert_eq!(guess_zugzwang_controller(board), Player::White);
+        assert_eq!(evaluate(board).guessed_zugzwang_controller(), Player::White);
+    }
+
+    #[test]
+    fn evaluate_prefers_the_center() {
+        let center = evaluate(play(&[3]));
+        let edge = evaluate(play(&[0]));
+        assert!(center.score() > edge.score());
+        assert_eq!(center.player(Player::White).open_groups_with(1), 7);
+        assert_eq!(center.player(Player::Black).open_groups().len(), 69 - 7);
+    }
+}
@@ -3,8 +3,9 @@
 */
 
 use std::cmp::Ordering;
+use std::fmt;
 
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
 pub enum Player {
     White, //Yellow in the GUI
     Black //Red in the GUI
@@ -19,12 +20,73 @@ impl Player {
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
 pub enum Square {
     Empty,
     Taken(Player)
 }
 
+//Name of a square in the usual notation, from a1 to g6
+pub fn square_name(column: usize, row: usize) -> String {
+    format!("{}{}", (b'a' + column as u8) as char, row + 1)
+}
+
+//A set of squares, stored with the same bit layout as the Board masks
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Default, Debug)]
+pub struct SquareSet(u64);
+
+impl SquareSet {
+    pub const EMPTY: Self = Self(0);
+    
+    pub(crate) fn from_mask(mask: u64) -> Self {
+        Self(mask & Board::BOARD_MASK)
+    }
+    
+    pub(crate) fn mask(self) -> u64 {
+        self.0
+    }
+    
+    pub fn contains(self, column: usize, row: usize) -> bool {
+        column < Board::WIDTH && row < Board::HEIGHT && self.0 & Board::square_mask(column, row) != 0
+    }
+    
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+    
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+    
+    //Squares are listed column by column, from the bottom to the top
+    pub fn iter(self) -> impl Iterator<Item = (usize, usize)> {
+        (0..Board::WIDTH)
+            .flat_map(|column| (0..Board::HEIGHT).map(move |row| (column, row)))
+            .filter(move |&(column, row)| self.contains(column, row))
+    }
+}
+
+impl fmt::Display for SquareSet {
+    //Squares of a same column are joined with slashes, for example "c2/c3, d4"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first_column = true;
+        for column in 0..Board::WIDTH {
+            let mut first_row = true;
+            for row in (0..Board::HEIGHT).filter(|&row| self.contains(column, row)) {
+                if first_row && !first_column {
+                    write!(f, ", ")?;
+                } else if !first_row {
+                    write!(f, "/")?;
+                }
+                write!(f, "{}", square_name(column, row))?;
+                first_row = false;
+                first_column = false;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct Board {
     current_player: u64,
@@ -48,15 +110,15 @@ impl Board {
     const BOTTOM: u64 = Self::bottom(Self::WIDTH);
     const BOARD_MASK: u64 = Self::BOTTOM * ((1 << Self::HEIGHT) - 1);
 
-    fn column_mask(column: usize) -> u64 {
+    pub(crate) fn column_mask(column: usize) -> u64 {
         ((1 << Self::HEIGHT) - 1) << (column * (Self::HEIGHT + 1))
     }
     
-    fn row_mask(row: usize) -> u64 {
+    pub(crate) fn row_mask(row: usize) -> u64 {
         Self::BOTTOM << row
     }
     
-    fn square_mask(column: usize, row: usize) -> u64 {
+    pub(crate) fn square_mask(column: usize, row: usize) -> u64 {
         Self::column_mask(column) & Self::row_mask(row)
     }
     
@@ -76,7 +138,7 @@ impl Board {
         }
     }
     
-    fn is_winning(self) -> bool {
+    pub(crate) fn is_winning(self) -> bool {
         let last_player = self.mask ^ self.current_player;
 
         let horizontal_overlap_1 = last_player & (last_player >> (Self::HEIGHT + 1));
@@ -106,12 +168,24 @@ impl Board {
         false
     }
     
-    fn winning_positions(self, player: Player) -> u64 {
-        let player_positions = if self.player_to_play() == player {
+    pub(crate) fn player_positions(self, player: Player) -> u64 {
+        if self.player_to_play() == player {
             self.current_player
         } else {
             self.current_player ^ self.mask
-        };
+        }
+    }
+    
+    pub(crate) fn empty_positions(self) -> u64 {
+        Self::BOARD_MASK ^ self.mask
+    }
+    
+    pub(crate) fn playable_positions(self) -> u64 {
+        (self.mask + Self::BOTTOM) & Self::BOARD_MASK
+    }
+    
+    pub(crate) fn winning_positions(self, player: Player) -> u64 {
+        let player_positions = self.player_positions(player);
 
         //Vertical
         let mut result = (player_positions << 1) & (player_positions << 2) & (player_positions << 3);
@@ -153,20 +227,17 @@ impl Board {
         if column >= Self::WIDTH || !self.can_play(column) {
             return false;
         }
-        self.winning_positions(player) & Self::column_mask(column) != 0
+        //The winning square has to be the playable square of the column
+        self.winning_positions(player) & self.playable_positions() & Self::column_mask(column) != 0
     }
     
     pub fn get_winning_move(self) -> Option<usize> {
-        let winning_positions = self.winning_positions(self.player_to_play());
+        let winning_positions = self.winning_positions(self.player_to_play()) & self.playable_positions();
         if winning_positions == 0 {
             return None;
         }
-        for column in 0..Self::WIDTH {
-            if winning_positions & Self::column_mask(column) != 0 {
-                return Some(column);
-            }
-        }
-        None //Invalid board, should I panic?
+        //None would mean an invalid board, should I panic?
+        (0..Self::WIDTH).find(|&column| winning_positions & Self::column_mask(column) != 0)
     }
     
     pub fn is_full(self) -> bool {
@@ -185,10 +256,11 @@ impl Board {
         if column >= Self::WIDTH {
             return false;
         }
-        let top_mask = (1 << Self::HEIGHT - 1) << (column * (Self::HEIGHT + 1));
+        let top_mask = (1 << (Self::HEIGHT - 1)) << (column * (Self::HEIGHT + 1));
         self.mask & top_mask == 0
     }
     
+    #[allow(clippy::result_unit_err)]
     pub fn make_move(self, column: usize) -> Result<Board, ()> {
         if !self.can_play(column) {
             return Err(())
@@ -200,6 +272,15 @@ impl Board {
         })
     }
     
+    //Plays on a playable square given as a mask, without any check
+    pub(crate) fn make_move_on(self, square: u64) -> Self {
+        Self {
+            current_player: self.current_player ^ self.mask,
+            mask: self.mask | square,
+            filled: self.filled + 1
+        }
+    }
+
     pub fn get_square(&self, column: usize, row: usize) -> Option<Square> {
         if column >= Self::WIDTH || row >= Self::HEIGHT {
             None
@@ -262,9 +343,36 @@ impl Board {
     }
 }
 
+impl fmt::Display for Board {
+    //Rows from the top, separated by slashes: X for White, O for Black and dots for empty
+    //squares
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in (0..Self::HEIGHT).rev() {
+            for column in 0..Self::WIDTH {
+                let square = match self.get_square(column, row).unwrap() {
+                    Square::Empty => '.',
+                    Square::Taken(Player::White) => 'X',
+                    Square::Taken(Player::Black) => 'O'
+                };
+                write!(f, "{}", square)?;
+            }
+            if row != 0 {
+                write!(f, "/")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PartialOrd for Board {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.row_major_comparison(*other))
+        Some(self.cmp(other))
     }
 }
 
@@ -272,4 +380,64 @@ impl Ord for Board {
     fn cmp(&self, other: &Self) -> Ordering {
         self.row_major_comparison(*other)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::play;
+
+    //A threat above an empty square cannot be played yet
+    #[test]
+    fn winning_moves_are_on_the_playable_squares() {
+        //White threatens d2, above Black's threat on d1
+        let board = play(&[6, 0, 0, 1, 1, 2, 2, 5]);
+        assert_eq!(board.player_to_play(), Player::White);
+        assert_ne!(board.winning_positions(Player::White) & Board::column_mask(3), 0);
+        assert!(!board.is_move_winning(3, Player::White));
+        assert!(board.is_move_winning(3, Player::Black));
+        assert_eq!(board.get_winning_move(), None);
+        assert_eq!(board.make_move(5).unwrap().get_winning_move(), Some(3));
+    }
+}
+
+//Helpers of the tests of the other modules
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::Board;
+
+    pub fn play(columns: &[usize]) -> Board {
+        columns.iter().fold(Board::new(), |board, &column| board.make_move(column).unwrap())
+    }
+
+    //Games played at random, the same ones at every run: the moves come from a linear
+    //congruential generator
+    pub struct RandomGames {
+        seed: u64
+    }
+
+    impl RandomGames {
+        pub fn new(seed: u64) -> Self {
+            Self { seed }
+        }
+
+        fn column(&mut self) -> usize {
+            self.seed = self.seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.seed >> 33) as usize % Board::WIDTH
+        }
+
+        //A game played until the board holds this many men, None when it ends before
+        pub fn position(&mut self, filled: usize) -> Option<Board> {
+            let mut board = Board::new();
+            while board.filled_squares() < filled {
+                let Ok(new_board) = board.make_move(self.column()) else {
+                    continue;
+                };
+                if new_board.is_endgame() {
+                    return None;
+                }
+                board = new_board;
+            }
+            Some(board)
+        }
+    }
+}
@@ -13,6 +13,7 @@ fn encode_square(board: Board, column: usize, row: usize) -> u8 {
     }
 }
 
+#[allow(clippy::identity_op)]
 fn collapse_position(board: Board) -> [u8; 14] {
     let mut output = [0; 14];
     for row in 0..Board::HEIGHT {
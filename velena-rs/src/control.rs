@@ -0,0 +1,203 @@
+/*
+ Not in the original program: lets a search be stopped and report its progress
+*/
+
+//The searches check the token between two developments, so they stop quickly but not
+//immediately. A stopped search returns what it found so far, like when it runs out of
+//computing resources.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::board::Board;
+
+//Shared between the thread running the search and the ones which may stop it
+#[derive(Clone, Default, Debug)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Progress {
+    nodes: usize,
+    elapsed: Duration,
+    best_move: Option<usize>,
+    numbers: Option<(u32, u32)>
+}
+
+impl Progress {
+    //Positions created or explored since the start of the search
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    //The most proving move of the root for the proof-number searches
+    pub fn best_move(&self) -> Option<usize> {
+        self.best_move
+    }
+
+    //Proof and disproof numbers of the root, None for the solver
+    pub fn numbers(&self) -> Option<(u32, u32)> {
+        self.numbers
+    }
+}
+
+pub type ProgressCallback<'a> = Box<dyn FnMut(&Progress) + 'a>;
+
+pub struct SearchControl<'a> {
+    pub token: CancellationToken,
+    pub on_progress: Option<ProgressCallback<'a>>,
+    pub interval: Duration //Minimum time between two progress reports
+}
+
+impl<'a> SearchControl<'a> {
+    pub fn new(token: CancellationToken) -> Self {
+        Self {
+            token,
+            on_progress: None,
+            interval: Duration::from_millis(100)
+        }
+    }
+
+    pub fn with_progress(token: CancellationToken, on_progress: impl FnMut(&Progress) + 'a) -> Self {
+        Self {
+            on_progress: Some(Box::new(on_progress)),
+            ..Self::new(token)
+        }
+    }
+}
+
+impl Default for SearchControl<'_> {
+    fn default() -> Self {
+        Self::new(CancellationToken::new())
+    }
+}
+
+impl fmt::Debug for SearchControl<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SearchControl")
+            .field("token", &self.token)
+            .field("on_progress", &self.on_progress.is_some())
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+//What the searches use internally. The nodes of the previous searches of the same call are
+//added to the reports.
+pub(crate) struct Monitor<'a> {
+    token: CancellationToken,
+    on_progress: Option<&'a mut dyn FnMut(&Progress)>,
+    interval: Duration,
+    start: Instant,
+    last_report: Instant,
+    previous_nodes: usize,
+    mirrored: bool, //The search works on the mirror of the board
+    counter: Option<(&'a AtomicUsize, usize)> //Shared with other threads, and what we added to it
+}
+
+impl<'a> Monitor<'a> {
+    pub(crate) fn new(control: &'a mut SearchControl<'_>) -> Self {
+        let now = Instant::now();
+        Self {
+            token: control.token.clone(),
+            on_progress: match control.on_progress.as_mut() {
+                Some(on_progress) => Some(on_progress.as_mut()),
+                None => None
+            },
+            interval: control.interval,
+            start: now,
+            last_report: now,
+            previous_nodes: 0,
+            mirrored: false,
+            counter: None
+        }
+    }
+
+    //Only checks the token, for the searches run by other searches
+    pub(crate) fn silent(token: CancellationToken) -> Self {
+        let now = Instant::now();
+        Self {
+            token,
+            on_progress: None,
+            interval: Duration::ZERO,
+            start: now,
+            last_report: now,
+            previous_nodes: 0,
+            mirrored: false,
+            counter: None
+        }
+    }
+
+    //For the searches run by this one in the same thread: they are stopped with it, and do
+    //not report
+    pub(crate) fn inner(&self) -> Monitor<'static> {
+        Monitor::silent(self.token.clone())
+    }
+
+    //Only adds the nodes of the search to a counter, for the searches run in other threads
+    pub(crate) fn counting(token: CancellationToken, counter: &'a AtomicUsize) -> Self {
+        Self {
+            counter: Some((counter, 0)),
+            ..Self::silent(token)
+        }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub(crate) fn add_previous_nodes(&mut self, nodes: usize) {
+        self.previous_nodes += nodes;
+    }
+
+    pub(crate) fn set_mirrored(&mut self, mirrored: bool) {
+        self.mirrored = mirrored;
+    }
+
+    //The move and the numbers are only computed when a report is due
+    pub(crate) fn report(&mut self, nodes: usize, position: impl FnOnce() -> (Option<usize>, Option<(u32, u32)>)) {
+        if let Some((counter, counted)) = self.counter.as_mut() {
+            counter.fetch_add(nodes - *counted, Ordering::Relaxed);
+            *counted = nodes;
+        }
+        let Some(on_progress) = self.on_progress.as_mut() else {
+            return;
+        };
+        let now = Instant::now();
+        if now.duration_since(self.last_report) < self.interval {
+            return;
+        }
+        self.last_report = now;
+
+        let (best_move, numbers) = position();
+        let mirrored = self.mirrored;
+        on_progress(&Progress {
+            nodes: self.previous_nodes + nodes,
+            elapsed: now.duration_since(self.start),
+            best_move: best_move.map(|column| if mirrored {
+                Board::WIDTH - 1 - column
+            } else {
+                column
+            }),
+            numbers
+        });
+    }
+}
@@ -0,0 +1,208 @@
+/*
+ Not in the original program: what the engine plays when nothing could be proved
+*/
+
+//Iterative deepening alpha-beta on the static evaluation of analysis::evaluate. Every
+//finished iteration gives a move, and the first one is always finished, so there is a legal
+//move even when the search is stopped early.
+
+use std::time::Instant;
+use crate::analysis;
+use crate::board::Board;
+use crate::control::CancellationToken;
+
+pub const DEFAULT_DEPTH: usize = 8;
+
+const WIN: i32 = 1_000_000;
+const CHECK_INTERVAL: usize = 1024;
+const COLUMN_ORDER: [usize; Board::WIDTH] = [3, 2, 4, 1, 5, 0, 6];
+
+struct AlphaBeta<'a> {
+    deadline: Option<Instant>,
+    token: &'a CancellationToken,
+    nodes: usize,
+    can_stop: bool,
+    stopped: bool
+}
+
+impl AlphaBeta<'_> {
+    //The previous move did not end the game
+    fn negamax(&mut self, board: Board, depth: usize, mut alpha: i32, beta: i32, ply: i32) -> i32 {
+        self.nodes += 1;
+        if self.can_stop && self.nodes.is_multiple_of(CHECK_INTERVAL)
+            && (self.token.is_cancelled() || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)) {
+            self.stopped = true;
+        }
+        if self.stopped {
+            return 0;
+        }
+        if board.get_winning_move().is_some() {
+            return WIN - ply;
+        }
+        if depth == 0 {
+            return analysis::evaluate(board).score_for(board.player_to_play());
+        }
+
+        let mut best = -WIN;
+        for column in COLUMN_ORDER {
+            let Ok(next) = board.make_move(column) else {
+                continue;
+            };
+            let score = if next.is_full() {
+                0
+            } else {
+                -self.negamax(next, depth - 1, -beta, -alpha, ply + 1)
+            };
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+
+    //The best move of the previous iteration is tried first
+    fn search_root(&mut self, board: Board, depth: usize, previous: Option<usize>, excluded: &[usize]) -> Option<(usize, i32)> {
+        if let Some(column) = board.get_winning_move() {
+            return Some((column, WIN));
+        }
+        let columns = previous.into_iter()
+            .chain(COLUMN_ORDER.into_iter().filter(|&column| Some(column) != previous))
+            .filter(|column| !excluded.contains(column));
+        let mut best: Option<(usize, i32)> = None;
+        let mut alpha = -WIN - 1;
+        for column in columns {
+            let Ok(next) = board.make_move(column) else {
+                continue;
+            };
+            let score = if next.is_full() {
+                0
+            } else {
+                -self.negamax(next, depth - 1, -WIN - 1, -alpha, 1)
+            };
+            if self.stopped {
+                return None;
+            }
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((column, score));
+                alpha = score;
+            }
+        }
+        best
+    }
+}
+
+//None only when the board is full. The search stops at the given depth, when the
+//deadline is reached, when it is cancelled or when the game is decided within its horizon.
+pub fn fallback_move(board: Board, depth: usize, deadline: Option<Instant>, token: &CancellationToken) -> Option<usize> {
+    fallback_move_excluding(board, depth, deadline, token, &[])
+}
+
+//The excluded moves, for example the ones a search proved to lose, are only played when
+//every other column is full
+pub fn fallback_move_excluding(board: Board, depth: usize, deadline: Option<Instant>, token: &CancellationToken,
+                               excluded: &[usize]) -> Option<usize> {
+    let excluded = if (0..Board::WIDTH).any(|column| board.can_play(column) && !excluded.contains(&column)) {
+        excluded
+    } else {
+        &[]
+    };
+    let mut search = AlphaBeta {
+        deadline,
+        token,
+        nodes: 0,
+        can_stop: false,
+        stopped: false
+    };
+    let mut best = None;
+    for depth in 1..=depth.max(1) {
+        let Some((column, score)) = search.search_root(board, depth, best, excluded) else {
+            break;
+        };
+        best = Some(column);
+        search.can_stop = true;
+        if score.abs() >= WIN - depth as i32 {
+            break;
+        }
+    }
+    best
+}
+
+//Value of each move for the player who plays it, None for the full columns. Unlike
+//fallback_move, every move gets its exact value at this depth, so there is no move to give
+//when the search is stopped: the result is then None.
+pub fn move_scores(board: Board, depth: usize, deadline: Option<Instant>, token: &CancellationToken) -> Option<[Option<i32>; Board::WIDTH]> {
+    let mut search = AlphaBeta {
+        deadline,
+        token,
+        nodes: 0,
+        can_stop: true,
+        stopped: false
+    };
+    let mut scores = [None; Board::WIDTH];
+    for (column, score) in scores.iter_mut().enumerate() {
+        let Ok(next) = board.make_move(column) else {
+            continue;
+        };
+        *score = Some(if board.is_move_winning(column, board.player_to_play()) {
+            WIN
+        } else if next.is_full() {
+            0
+        } else {
+            -search.negamax(next, depth.max(1) - 1, -WIN - 1, WIN + 1, 1)
+        });
+    }
+    (!search.stopped).then_some(scores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::testing::play;
+
+    #[test]
+    fn never_plays_a_full_column() {
+        let board = play(&[0, 0, 0, 0, 0, 0]);
+        let best = fallback_move(board, DEFAULT_DEPTH, None, &CancellationToken::new());
+        assert!(best.is_some_and(|column| board.can_play(column)));
+    }
+
+    #[test]
+    fn wins_and_blocks() {
+        assert_eq!(fallback_move(play(&[0, 1, 0, 1, 0, 1]), DEFAULT_DEPTH, None, &CancellationToken::new()), Some(0));
+        assert_eq!(fallback_move(play(&[0, 1, 0, 1, 0]), DEFAULT_DEPTH, None, &CancellationToken::new()), Some(0));
+    }
+
+    #[test]
+    fn cancelled_search_still_moves() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let board = play(&[3, 3, 2]);
+        assert!(fallback_move(board, 20, None, &token).is_some_and(|column| board.can_play(column)));
+    }
+
+    #[test]
+    fn excluded_moves() {
+        let board = play(&[0, 1, 0, 1, 0]);
+        let token = CancellationToken::new();
+        assert_ne!(fallback_move_excluding(board, 4, None, &token, &[0]), Some(0));
+        let board = play(&[0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 4, 4, 4, 4, 4, 4, 5, 5, 5, 5, 5, 5, 3]);
+        assert_eq!(fallback_move_excluding(board, 4, None, &token, &[3, 6]).map(|column| column == 3 || column == 6), Some(true));
+    }
+
+    #[test]
+    fn stopped_move_scores() {
+        let board = play(&[3, 3, 2]);
+        let scores = move_scores(board, 4, None, &CancellationToken::new()).unwrap();
+        assert!(scores.iter().all(Option::is_some));
+        let token = CancellationToken::new();
+        token.cancel();
+        assert_eq!(move_scores(board, 12, None, &token), None);
+    }
+
+    #[test]
+    fn finished_board() {
+        assert!(fallback_move(play(&[0, 1, 0, 1, 0, 1, 0]), DEFAULT_DEPTH, None, &CancellationToken::new()).is_some());
+    }
+}
@@ -2,12 +2,14 @@
  connect4.c
  */
 
-use crate::board::Board;
+use std::fmt;
+use crate::board::{self, Board};
 
-pub const _GROUP_COUNT: usize = 69;
+pub const GROUP_COUNT: usize = 69;
 
-const fn _generate_groups() -> [[(usize, usize); 4]; _GROUP_COUNT] {
-    let mut output = [[(0, 0); 4]; _GROUP_COUNT];
+#[allow(clippy::identity_op)]
+const fn generate_groups() -> [[(usize, usize); 4]; GROUP_COUNT] {
+    let mut output = [[(0, 0); 4]; GROUP_COUNT];
     let mut i = 0;
     
     // Horizontal lines
@@ -77,4 +79,91 @@ const fn _generate_groups() -> [[(usize, usize); 4]; _GROUP_COUNT] {
     output
 }
 
-pub const _GROUPS: [[(usize, usize); 4]; _GROUP_COUNT] = _generate_groups();
\ No newline at end of file
+pub const GROUPS: [[(usize, usize); 4]; GROUP_COUNT] = generate_groups();
+
+const fn generate_group_masks() -> [u64; GROUP_COUNT] {
+    let mut output = [0; GROUP_COUNT];
+    let mut i = 0;
+    while i < GROUP_COUNT {
+        let mut j = 0;
+        while j < 4 {
+            let (x, y) = GROUPS[i][j];
+            output[i] |= 1 << (x * (Board::HEIGHT + 1) + y);
+            j += 1;
+        }
+        i += 1;
+    }
+    output
+}
+
+//Name of a group from its first and last squares, for example "a1-d4"
+pub fn group_name(group: usize) -> String {
+    let (first_column, first_row) = GROUPS[group][0];
+    let (last_column, last_row) = GROUPS[group][3];
+    format!("{}-{}", board::square_name(first_column, first_row), board::square_name(last_column, last_row))
+}
+
+//Bitboards of the groups, using the same layout as the Board masks
+pub(crate) const GROUP_MASKS: [u64; GROUP_COUNT] = generate_group_masks();
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Default, Debug)]
+pub struct GroupSet(u128);
+
+impl GroupSet {
+    pub const EMPTY: Self = Self(0);
+    
+    pub(crate) fn single(group: usize) -> Self {
+        Self(1 << group)
+    }
+    
+    pub fn contains(self, group: usize) -> bool {
+        group < GROUP_COUNT && self.0 & (1 << group) != 0
+    }
+    
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+    
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+    
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+    
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+    
+    pub fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+    
+    pub fn iter(self) -> impl Iterator<Item = usize> {
+        (0..GROUP_COUNT).filter(move |&group| self.contains(group))
+    }
+    
+    //Every group which contains all the squares of the given bitboard
+    pub(crate) fn containing(squares: u64) -> Self {
+        let mut result = 0;
+        for (group, mask) in GROUP_MASKS.iter().enumerate() {
+            if mask & squares == squares {
+                result |= 1 << group;
+            }
+        }
+        Self(result)
+    }
+}
+
+impl fmt::Display for GroupSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, group) in self.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", group_name(group))?;
+        }
+        Ok(())
+    }
+}
@@ -2,13 +2,18 @@
  heurist.c, pnsearch.c
 */
 
-use std::cell::RefCell;
+use std::array;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::mem;
-use std::ops::{Deref, DerefMut};
-use std::rc::{Rc, Weak};
-use crate::board::Board;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::board::{Board, Player};
+use crate::control::{CancellationToken, Monitor, SearchControl};
+use crate::strategy::{self, Outcome};
+use crate::table::{Replacement, TranspositionTable};
 
 #[derive(Copy, Clone, Debug)]
 enum NodeType {
@@ -23,35 +28,60 @@ enum NodeState {
     Expanded
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 enum NodeValue {
     Disproved,
     Unknown,
     Proved
 }
 
-#[derive(Debug)]
+impl NodeValue {
+    fn from_numbers(proof: u32, disproof: u32) -> Self {
+        if proof == 0 {
+            NodeValue::Proved
+        } else if disproof == 0 {
+            NodeValue::Disproved
+        } else {
+            NodeValue::Unknown
+        }
+    }
+}
+
+const MAX_VALUE: u32 = 200000000;
+
+//Index of a node in the arena
+type NodeId = u32;
+const NO_NODE: NodeId = NodeId::MAX;
+
+#[derive(Copy, Clone, Debug)]
 struct Node {
     board: Board,
-    child: [Option<Rc<RefCell<Node>>>; Board::WIDTH],
-    parent: [Option<Weak<RefCell<Node>>>; Board::WIDTH],
+    child: [NodeId; Board::WIDTH],
+    first_parent: u32, //Index of the first link to a parent, or NO_NODE
+    proof: u32,
+    disproof: u32,
     state: NodeState,
     value: NodeValue,
-    proof: usize,
-    disproof: usize,
     node_type: NodeType
 }
 
+//Nodes may have many parents because of transpositions, so we keep linked lists of them
+#[derive(Copy, Clone, Debug)]
+struct ParentLink {
+    parent: NodeId,
+    next: u32
+}
+
 impl Node {
     fn new(board: Board, node_type: NodeType) -> Self {
         Self {
             board,
-            child: Default::default(),
-            parent: Default::default(),
-            state: NodeState::NotEvaluated,
-            value: NodeValue::Unknown,
+            child: [NO_NODE; Board::WIDTH],
+            first_parent: NO_NODE,
             proof: 0,
             disproof: 0,
+            state: NodeState::NotEvaluated,
+            value: NodeValue::Unknown,
             node_type
         }
     }
@@ -85,13 +115,24 @@ impl Node {
             return Some(win_immediately);
         }
 
-        self.value = NodeValue::Unknown;
+        //The player who just moved may be able to prove the position with Allis' rules
+        self.value = match strategy::find_strategy(self.board).map(|strategy| strategy.outcome()) {
+            Some(Outcome::Win) => match self.node_type {
+                NodeType::And => NodeValue::Proved,
+                NodeType::Or => NodeValue::Disproved
+            },
+            //A draw is enough to prove the root when fighting, and to disprove it otherwise
+            Some(Outcome::AtLeastDraw) => match (self.node_type, fight) {
+                (NodeType::And, true) => NodeValue::Proved,
+                (NodeType::Or, false) => NodeValue::Disproved,
+                _ => NodeValue::Unknown
+            },
+            None => NodeValue::Unknown
+        };
         None
     }
 
-    fn set_proof_and_disproof_numbers(&mut self) {
-        const MAX_VALUE: usize = 200000000;
-
+    fn set_proof_and_disproof_numbers(&mut self, nodes: &[Node]) {
         match self.state {
             NodeState::Expanded => {
                 match self.node_type {
@@ -99,14 +140,12 @@ impl Node {
                         self.proof = 0;
                         self.disproof = MAX_VALUE;
 
-                        for column in 0..Board::WIDTH {
-                            if let Some(child) = self.child[column].as_ref() {
-                                let child_borrow = child.borrow();
-                                //We need to prove every child
-                                self.proof += child_borrow.proof;
-                                //We only need to disprove one child
-                                self.disproof = Ord::min(self.disproof, child_borrow.disproof);
-                            }
+                        for &child in self.child.iter().filter(|&&child| child != NO_NODE) {
+                            let child = &nodes[child as usize];
+                            //We need to prove every child
+                            self.proof = self.proof.saturating_add(child.proof);
+                            //We only need to disprove one child
+                            self.disproof = Ord::min(self.disproof, child.disproof);
                         }
 
                         if self.disproof == 0 {
@@ -118,14 +157,12 @@ impl Node {
                         self.proof = MAX_VALUE;
                         self.disproof = 0;
 
-                        for column in 0..Board::WIDTH {
-                            if let Some(child) = self.child[column].as_ref() {
-                                let child_borrow = child.borrow();
-                                //We only need to prove one child
-                                self.proof = Ord::min(self.proof, child_borrow.proof);
-                                //We only need to disprove every child
-                                self.disproof += child_borrow.disproof;
-                            }
+                        for &child in self.child.iter().filter(|&&child| child != NO_NODE) {
+                            let child = &nodes[child as usize];
+                            //We only need to prove one child
+                            self.proof = Ord::min(self.proof, child.proof);
+                            //We only need to disprove every child
+                            self.disproof = self.disproof.saturating_add(child.disproof);
                         }
 
                         if self.proof == 0 {
@@ -171,144 +208,901 @@ impl Node {
     }
 }
 
-fn select_most_proving_node<'a>(mut node: Rc<RefCell<Node>>, best_move: &mut Option<usize>) -> Rc<RefCell<Node>> {
-    const NODE_SEQUENCE_ORDER: [usize; Board::WIDTH] = [3, 2, 4, 5, 1, 0, 6];
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub enum Algorithm {
+    #[default]
+    ProofNumber, //Best-first, keeps the whole tree in memory
+    DepthFirst, //df-pn, only keeps a bounded transposition table
+    TwoLevel //PN², the leaves get their numbers from a smaller search which is then discarded
+}
 
-    let mut depth = 0;
-    while node.borrow().state == NodeState::Expanded {
-        let mut good_child = None;
-        {
-            let node_borrow = node.borrow();
-            match node_borrow.node_type {
-                NodeType::And => {
-                    for column in NODE_SEQUENCE_ORDER {
-                        if let Some(child) = node_borrow.child[column].as_ref() {
-                            if child.borrow().disproof == node_borrow.disproof {
-                                good_child = Some(column);
-                                break;
-                            }
-                        }
-                    }
+//What the search tries to prove for the player to move. Internally, the original program
+//calls "fight" the search of at least a draw.
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub enum Goal {
+    #[default]
+    Win, //A draw counts as a failure
+    AtLeastDraw,
+    //Look for a win first, then for at least a draw. Once the search of a draw has started,
+    //the best move is never one it proved to lose, unless every move loses.
+    AvoidLoss
+}
+
+//Value of the root: for AtLeastDraw, a draw is enough to prove it
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Verdict {
+    Proved,
+    Disproved,
+    Unknown
+}
+
+impl Verdict {
+    fn from_numbers(proof: u32, disproof: u32) -> Self {
+        NodeValue::from_numbers(proof, disproof).into()
+    }
+}
+
+impl From<NodeValue> for Verdict {
+    fn from(value: NodeValue) -> Self {
+        match value {
+            NodeValue::Proved => Verdict::Proved,
+            NodeValue::Disproved => Verdict::Disproved,
+            NodeValue::Unknown => Verdict::Unknown
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SearchResult {
+    verdict: Verdict,
+    principal_variation: Vec<usize>,
+    proof: u32,
+    disproof: u32,
+    nodes: usize,
+    developments: usize,
+    losing_moves: Vec<usize>
+}
+
+impl SearchResult {
+    pub fn verdict(&self) -> Verdict {
+        self.verdict
+    }
+
+    //The move proving the root, or the most promising one when it is unknown
+    pub fn best_move(&self) -> Option<usize> {
+        self.principal_variation.first().copied()
+    }
+
+    //The most proving path, which is a winning line once the root is proved
+    pub fn principal_variation(&self) -> &[usize] {
+        &self.principal_variation
+    }
+
+    pub fn proof(&self) -> u32 {
+        self.proof
+    }
+
+    pub fn disproof(&self) -> u32 {
+        self.disproof
+    }
+
+    //Positions evaluated, in the first level tree for PN²
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    //Nodes developed, or positions searched for df-pn
+    pub fn developments(&self) -> usize {
+        self.developments
+    }
+
+    //Moves of the root proved to lose, among the ones searched. Only the searches of at least
+    //a draw find them, the search of a win cannot tell a loss from a draw.
+    pub fn losing_moves(&self) -> &[usize] {
+        &self.losing_moves
+    }
+}
+
+//Resources the proof-number search may use before giving up
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SearchLimits {
+    pub nodes: usize, //Number of nodes created, including the ones of the second level of PN²
+    pub deadline: Option<Instant>,
+    pub memory: Option<usize>, //In bytes, for the nodes and the transposition table
+    //Above 1, the moves of the root are searched in parallel. They share the node budget,
+    //and each thread gets a share of the memory.
+    pub threads: usize
+}
+
+impl SearchLimits {
+    //Approximate memory used by each node, including its parent link and its transposition
+    //table entry
+    const NODE_MEMORY: usize = mem::size_of::<Node>() + mem::size_of::<ParentLink>() + mem::size_of::<(u64, NodeId)>() + 1;
+
+    //The nodes created by this search, and the nodes of the tree, which may have been kept
+    //from previous searches
+    fn exceeded(&self, nodes: usize, tree_size: usize) -> bool {
+        nodes > self.nodes
+            || self.memory.is_some_and(|memory| tree_size * Self::NODE_MEMORY > memory)
+            || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+impl Default for SearchLimits {
+    //The budget of the original program
+    fn default() -> Self {
+        Self {
+            nodes: 2800,
+            deadline: None,
+            memory: None,
+            threads: 1
+        }
+    }
+}
+
+const NODE_SEQUENCE_ORDER: [usize; Board::WIDTH] = [3, 2, 4, 5, 1, 0, 6];
+
+//The nodes are stored in an arena and refer to each other by index. Transposed positions
+//share the same node, found through the table of the tree, which unlike the shared
+//transposition table never loses a position.
+struct Tree {
+    nodes: Vec<Node>,
+    parents: Vec<ParentLink>,
+    table: HashMap<u64, NodeId>
+}
+
+impl Tree {
+    fn new(root: Node) -> Self {
+        let mut table = HashMap::new();
+        table.insert(root.board.key(), 0);
+        Self {
+            nodes: vec![root],
+            parents: Vec::new(),
+            table
+        }
+    }
+
+    //The subtree of an expanded node of the same type as the root, which becomes the new root.
+    //The values of the nodes depend on the root player, so the subtree stays valid as is.
+    fn reroot(self, board: Board) -> Option<Tree> {
+        let &root = self.table.get(&board.key())?;
+        let old_root = &self.nodes[root as usize];
+        if !matches!(old_root.node_type, NodeType::Or) || old_root.state != NodeState::Expanded {
+            return None;
+        }
+
+        //Copies the nodes reachable from the new root, in breadth-first order
+        let mut new_ids = vec![NO_NODE; self.nodes.len()];
+        let mut tree = Tree {
+            nodes: Vec::new(),
+            parents: Vec::new(),
+            table: HashMap::new()
+        };
+        new_ids[root as usize] = 0;
+        tree.nodes.push(self.nodes[root as usize]);
+        let mut next = 0;
+        while next < tree.nodes.len() {
+            for column in 0..Board::WIDTH {
+                let child = tree.nodes[next].child[column];
+                if child == NO_NODE {
+                    continue;
                 }
-                NodeType::Or => {
-                    for column in NODE_SEQUENCE_ORDER {
-                        if let Some(child) = node_borrow.child[column].as_ref() {
-                            if child.borrow().proof == node_borrow.proof {
-                                good_child = Some(column);
-                                break;
-                            }
-                        }
-                    }
+                if new_ids[child as usize] == NO_NODE {
+                    new_ids[child as usize] = tree.nodes.len() as NodeId;
+                    tree.nodes.push(self.nodes[child as usize]);
                 }
+                tree.nodes[next].child[column] = new_ids[child as usize];
             }
+            next += 1;
         }
-        let good_child = good_child.unwrap();
-        if depth == 0 {
-            *best_move = Some(good_child);
+
+        for id in 0..tree.nodes.len() {
+            let node = &mut tree.nodes[id];
+            node.first_parent = NO_NODE;
+            tree.table.insert(node.board.key(), id as NodeId);
         }
-        let child = node.borrow().child[good_child].as_ref().unwrap().clone();
-        node = child;
-        depth += 1;
+        for id in 0..tree.nodes.len() as NodeId {
+            let children = tree.nodes[id as usize].child;
+            for (column, &child) in children.iter().enumerate() {
+                //Both orientations of a symmetrical position lead to the same child
+                if child != NO_NODE && !children[..column].contains(&child) {
+                    tree.add_parent(child, id);
+                }
+            }
+        }
+        Some(tree)
     }
 
-    node
-}
+    fn add_parent(&mut self, node: NodeId, parent: NodeId) {
+        let node = &mut self.nodes[node as usize];
+        self.parents.push(ParentLink {
+            parent,
+            next: node.first_parent
+        });
+        node.first_parent = (self.parents.len() - 1) as u32;
+    }
 
-fn develop(node: Rc<RefCell<Node>>, nodes: &mut HashMap<u64, Rc<RefCell<Node>>>, 
-           root_node_type: NodeType, fight: bool, nodes_expanded: &mut usize) {
-    let mut node_borrow = node.borrow_mut();
+    fn set_proof_and_disproof_numbers(&mut self, node: NodeId) {
+        let mut updated = self.nodes[node as usize];
+        updated.set_proof_and_disproof_numbers(&self.nodes);
+        self.nodes[node as usize] = updated;
+    }
+
+    //The child through which the numbers of an expanded node are obtained
+    fn most_proving_child(&self, node: NodeId) -> usize {
+        let current = &self.nodes[node as usize];
+        NODE_SEQUENCE_ORDER.into_iter().find(|&column| {
+            let child = current.child[column];
+            child != NO_NODE && match current.node_type {
+                NodeType::And => self.nodes[child as usize].disproof == current.disproof,
+                NodeType::Or => self.nodes[child as usize].proof == current.proof
+            }
+        }).unwrap()
+    }
 
-    node_borrow.state = NodeState::Expanded;
+    fn select_most_proving_node(&self) -> NodeId {
+        let mut node = 0;
+        while self.nodes[node as usize].state == NodeState::Expanded {
+            node = self.nodes[node as usize].child[self.most_proving_child(node)];
+        }
 
-    for column in 0..Board::WIDTH {
-        if let Ok(mut new_board) = node_borrow.board.make_move(column) {
-            let mut symmetric = new_board.symmetric_board();
+        node
+    }
 
-            let backtrace = if symmetric < new_board {
-                mem::swap(&mut new_board, &mut symmetric);
+    //The most proving path, with the columns seen from the root. Transposed children may be
+    //stored mirrored compared to the move leading to them.
+    fn principal_variation(&self) -> Vec<usize> {
+        let mut variation = Vec::new();
+        let mut node = 0;
+        let mut mirrored = false;
+        while self.nodes[node as usize].state == NodeState::Expanded {
+            let column = self.most_proving_child(node);
+            let child = self.nodes[node as usize].child[column];
+            variation.push(if mirrored {
                 Board::WIDTH - 1 - column
             } else {
                 column
-            };
-            
-            match nodes.entry(new_board.key()) {
-                Entry::Occupied(e) => {
-                    let cached_child = e.get();
-                    cached_child.borrow_mut().parent[backtrace] = Some(Rc::downgrade(&node));
-                    node_borrow.child[column] = Some(cached_child.clone());
+            });
+            if self.nodes[node as usize].board.make_move(column).unwrap() != self.nodes[child as usize].board {
+                mirrored = !mirrored;
+            }
+            node = child;
+        }
+        variation
+    }
+
+    //With second level limits, the new children are searched with PN² before being added,
+    //and the nodes of these searches count in the budget. Once it is spent, or the search is
+    //stopped, the remaining children keep their evaluation. The new children found in the
+    //table get its numbers instead.
+    #[allow(clippy::too_many_arguments)]
+    fn develop(&mut self, node: NodeId, root_node_type: NodeType, fight: bool, nodes_expanded: &mut usize,
+               second_level: Option<&SearchLimits>, mut table: Option<&mut SharedTable>, monitor: &Monitor) {
+        self.nodes[node as usize].state = NodeState::Expanded;
+        let board = self.nodes[node as usize].board;
+        let child_type = match self.nodes[node as usize].node_type {
+            NodeType::And => NodeType::Or,
+            NodeType::Or => NodeType::And
+        };
+
+        for column in 0..Board::WIDTH {
+            if let Ok(mut new_board) = board.make_move(column) {
+                let symmetric = new_board.symmetric_board();
+                if symmetric < new_board {
+                    new_board = symmetric;
                 }
-                Entry::Vacant(e) => {
-                    *nodes_expanded += 1;
-                    
-                    let mut child = Node::new(new_board, match node_borrow.node_type {
-                        NodeType::And => NodeType::Or,
-                        NodeType::Or => NodeType::And
-                    });
-                    child.parent[backtrace] = Some(Rc::downgrade(&node));
-                    let child = Rc::new(RefCell::new(child));
-                    e.insert(child.clone());
-                    node_borrow.child[column] = Some(child);
+
+                let child = match self.table.entry(new_board.key()) {
+                    Entry::Occupied(e) => *e.get(),
+                    Entry::Vacant(e) => {
+                        *nodes_expanded += 1;
+
+                        let child = self.nodes.len() as NodeId;
+                        self.nodes.push(Node::new(new_board, child_type));
+                        e.insert(child);
+                        child
+                    }
+                };
+                //Both orientations of a symmetrical position lead to the same child
+                if !self.nodes[node as usize].child.contains(&child) {
+                    self.add_parent(child, node);
+                }
+                self.nodes[node as usize].child[column] = child;
+            }
+        }
+
+        for column in 0..Board::WIDTH {
+            let child = self.nodes[node as usize].child[column];
+            //Transposed children already have their numbers
+            if child != NO_NODE && self.nodes[child as usize].state == NodeState::NotEvaluated {
+                self.nodes[child as usize].evaluate(root_node_type, fight);
+                self.set_proof_and_disproof_numbers(child);
+
+                let known = match table.as_deref_mut() {
+                    Some(table) if self.nodes[child as usize].value == NodeValue::Unknown => table.lookup(self.nodes[child as usize].board),
+                    _ => None
+                };
+                if let Some((proof, disproof)) = known {
+                    let child = &mut self.nodes[child as usize];
+                    child.value = NodeValue::from_numbers(proof, disproof);
+                    child.proof = proof;
+                    child.disproof = disproof;
+                } else if let Some(limits) = second_level {
+                    if self.nodes[child as usize].value == NodeValue::Unknown && !limits.exceeded(*nodes_expanded, 0)
+                        && !monitor.is_cancelled() {
+                        let limits = SearchLimits {
+                            nodes: second_level_size(self.nodes.len(), limits).min(limits.nodes - *nodes_expanded),
+                            deadline: limits.deadline,
+                            memory: None,
+                            threads: 1
+                        };
+                        let mut tree = Tree::new(Node {
+                            first_parent: NO_NODE,
+                            ..self.nodes[child as usize]
+                        });
+                        let result = heuristic_proof_number_search(&mut tree, root_node_type, fight, &limits, None, None,
+                                                                   &mut monitor.inner());
+                        *nodes_expanded += result.nodes;
+                        let child = &mut self.nodes[child as usize];
+                        child.value = tree.nodes[0].value;
+                        child.proof = tree.nodes[0].proof;
+                        child.disproof = tree.nodes[0].disproof;
+                    }
                 }
             }
         }
     }
 
-    for child in &node_borrow.child {
-        if let Some(child) = child {
-            let mut child_borrow = child.borrow_mut();
-            child_borrow.evaluate(root_node_type, fight);
-            child_borrow.set_proof_and_disproof_numbers();
+    //Keeps the numbers of the searched positions for the next searches
+    fn store_expanded(&self, table: &mut SharedTable) {
+        for node in self.nodes.iter().filter(|node| node.state == NodeState::Expanded) {
+            table.store(node.board, node.proof, node.disproof);
         }
     }
-}
 
-fn update_ancestors(node: Rc<RefCell<Node>>) {
-    node.borrow_mut().set_proof_and_disproof_numbers();
-    for column in 0..Board::WIDTH {
-        if let Some(parent) = &node.borrow().parent[column] {
-            update_ancestors(parent.upgrade().unwrap());
+    //Deepest nodes are updated first, so that every ancestor is updated once, after all its
+    //children. We stop going up when the numbers of a node do not change.
+    fn update_ancestors(&mut self, node: NodeId) {
+        let mut pending = BinaryHeap::new();
+        let mut queued = HashSet::new();
+        pending.push((self.nodes[node as usize].board.filled_squares(), node));
+        queued.insert(node);
+
+        while let Some((_, node)) = pending.pop() {
+            let (proof, disproof) = (self.nodes[node as usize].proof, self.nodes[node as usize].disproof);
+            self.set_proof_and_disproof_numbers(node);
+            if self.nodes[node as usize].proof == proof && self.nodes[node as usize].disproof == disproof {
+                continue;
+            }
+
+            let mut link = self.nodes[node as usize].first_parent;
+            while link != NO_NODE {
+                let ParentLink { parent, next } = self.parents[link as usize];
+                if queued.insert(parent) {
+                    pending.push((self.nodes[parent as usize].board.filled_squares(), parent));
+                }
+                link = next;
+            }
         }
     }
 }
 
-fn heuristic_proof_number_search(root: Rc<RefCell<Node>>, nodes: &mut HashMap<u64, Rc<RefCell<Node>>>, fight: bool) -> Option<usize> {
-    const MAX_NODE_COUNT: usize = 2800;
-    
+//Size of the second level trees of PN², a fraction of the size of the first level tree as
+//described by Breuker. The fraction stays small until the first level tree gets close to
+//its budget, otherwise PN² would be far slower than the plain search.
+fn second_level_size(first_level_size: usize, limits: &SearchLimits) -> usize {
+    let size = first_level_size as f64;
+    let (a, b) = (limits.nodes as f64, limits.nodes as f64 / 4.0 + 1.0);
+    let fraction = 1.0 / (1.0 + ((a - size) / b).exp());
+    ((size * fraction).ceil() as usize).max(1)
+}
+
+//The root of the tree may already be evaluated when it is the leaf of a PN² search
+fn heuristic_proof_number_search(tree: &mut Tree, root_node_type: NodeType, fight: bool, limits: &SearchLimits,
+                                 second_level: Option<&SearchLimits>, mut table: Option<&mut SharedTable>,
+                                 monitor: &mut Monitor) -> SearchResult {
     let mut nodes_expanded = 0;
+    let mut developments = 0;
     
-    let (mut best_move, root_type) = {
-        let mut root_borrow = root.borrow_mut();
-        let root_type = root_borrow.node_type;
-        let best_move = root_borrow.evaluate(root_type, fight);
-        root_borrow.set_proof_and_disproof_numbers();
-        (best_move, root_type)
-    };
+    let mut winning_move = None;
+    if tree.nodes[0].state == NodeState::NotEvaluated {
+        winning_move = tree.nodes[0].evaluate(root_node_type, fight);
+        tree.set_proof_and_disproof_numbers(0);
+    }
     
     //Loop until the root is proved, disproved, or we run out of computing resources
-    while {
-        let root_borrow = root.borrow();
-        root_borrow.proof != 0 && root_borrow.disproof != 0
-    } && nodes_expanded <= MAX_NODE_COUNT {
-        let most_proving_node = select_most_proving_node(root.clone(), &mut best_move);
-        develop(most_proving_node.clone(), nodes, root_type, fight, &mut nodes_expanded);
-        update_ancestors(most_proving_node);
-    }
-
-    {
-        let mut root_borrow = root.borrow_mut();
-        if root_borrow.proof == 0 {
-            root_borrow.deref_mut().value = NodeValue::Proved;
-        } else if root_borrow.disproof == 0 {
-            root_borrow.deref_mut().value = NodeValue::Disproved;
-        } else {
-            root_borrow.deref_mut().value = NodeValue::Unknown;
-        }
+    while tree.nodes[0].proof != 0 && tree.nodes[0].disproof != 0 && !limits.exceeded(nodes_expanded, tree.nodes.len())
+        && !monitor.is_cancelled() {
+        let most_proving_node = tree.select_most_proving_node();
+        tree.develop(most_proving_node, root_node_type, fight, &mut nodes_expanded, second_level, table.as_deref_mut(), monitor);
+        tree.update_ancestors(most_proving_node);
+        developments += 1;
+        monitor.report(nodes_expanded, || {
+            let root = &tree.nodes[0];
+            (Some(tree.most_proving_child(0)), Some((root.proof, root.disproof)))
+        });
     }
+
+    let root = tree.nodes[0];
+    let value = NodeValue::from_numbers(root.proof, root.disproof);
+    tree.nodes[0].value = value;
     
-    best_move
+    SearchResult {
+        verdict: value.into(),
+        //The root may be proved by an immediate win, without being expanded
+        principal_variation: winning_move.map_or_else(|| tree.principal_variation(), |column| vec![column]),
+        proof: root.proof,
+        disproof: root.disproof,
+        nodes: nodes_expanded,
+        developments,
+        losing_moves: Vec::new()
+    }
+}
+
+//A searched tree, kept for debugging. The boards are stored in their canonical orientation,
+//so the root may be the mirror of the searched board, and the columns of the edges are seen
+//from the board of their parent.
+pub struct ProofTree(Tree);
+
+impl ProofTree {
+    pub fn len(&self) -> usize {
+        self.0.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.nodes.is_empty()
+    }
+
+    //Nodes on the path to the most proving node
+    fn most_proving_path(&self) -> Vec<bool> {
+        let mut path = vec![false; self.0.nodes.len()];
+        let mut node = 0;
+        path[0] = true;
+        while self.0.nodes[node as usize].state == NodeState::Expanded {
+            node = self.0.nodes[node as usize].child[self.0.most_proving_child(node)];
+            path[node as usize] = true;
+        }
+        path
+    }
+
+    //Graphviz graph, with the most proving path in red
+    pub fn to_dot(&self) -> String {
+        let path = self.most_proving_path();
+        let mut dot = String::from("digraph proof_tree {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for (id, node) in self.0.nodes.iter().enumerate() {
+            let color = if path[id] { ", color=red" } else { "" };
+            dot += &format!("    n{} [label=\"{:?} {:?} {:?}\\nproof {} disproof {}\\n{}\"{}];\n",
+                            id, node.node_type, node.state, node.value, node.proof, node.disproof,
+                            node.board.to_string().replace('/', "\\n"), color);
+        }
+        for (id, node) in self.0.nodes.iter().enumerate() {
+            for (column, &child) in node.child.iter().enumerate().filter(|&(_, &child)| child != NO_NODE) {
+                let color = if path[id] && path[child as usize] { ", color=red" } else { "" };
+                dot += &format!("    n{} -> n{} [label=\"{}\"{}];\n", id, child, column, color);
+            }
+        }
+        dot += "}\n";
+        dot
+    }
+
+    pub fn to_json(&self) -> String {
+        let path = self.most_proving_path();
+        let nodes: Vec<String> = self.0.nodes.iter().enumerate().map(|(id, node)| {
+            let children: Vec<String> = node.child.iter().enumerate()
+                .filter(|&(_, &child)| child != NO_NODE)
+                .map(|(column, child)| format!("{{\"column\":{},\"node\":{}}}", column, child))
+                .collect();
+            format!("{{\"id\":{},\"board\":\"{}\",\"type\":\"{:?}\",\"state\":\"{:?}\",\"value\":\"{:?}\",\"proof\":{},\"disproof\":{},\"most_proving\":{},\"children\":[{}]}}",
+                    id, node.board, node.node_type, node.state, node.value, node.proof, node.disproof, path[id], children.join(","))
+        }).collect();
+        format!("{{\"nodes\":[{}]}}", nodes.join(","))
+    }
+}
+
+//The shared transposition table, with the context of the current search
+struct SharedTable<'a> {
+    table: &'a mut TranspositionTable,
+    context: u8
+}
+
+impl<'a> SharedTable<'a> {
+    fn new(table: &'a mut TranspositionTable, root_player: Player, fight: bool) -> Self {
+        Self {
+            table,
+            context: fight as u8 | ((root_player == Player::White) as u8) << 1
+        }
+    }
+
+    fn store(&mut self, board: Board, proof: u32, disproof: u32) {
+        self.table.store(board.key(), self.context, proof, disproof);
+    }
+
+    fn lookup(&mut self, board: Board) -> Option<(u32, u32)> {
+        self.table.lookup(board.key(), self.context)
+    }
+
+    //Does not count in the statistics of the table
+    fn get(&self, board: Board) -> Option<(u32, u32)> {
+        self.table.get(board.key(), self.context)
+    }
+}
+
+//Depth-first proof-number search, as described by Nagai. The numbers of the positions are
+//only kept in a transposition table of bounded size, at the cost of searching again the
+//positions which were lost.
+struct DepthFirstSearch<'a> {
+    table: SharedTable<'a>,
+    root: Board,
+    root_progress: (Option<usize>, Option<(u32, u32)>), //Best move and numbers of the root
+    root_node_type: NodeType,
+    fight: bool,
+    limits: &'a SearchLimits,
+    nodes_expanded: usize,
+    developments: usize
+}
+
+impl<'a> DepthFirstSearch<'a> {
+    fn new(table: SharedTable<'a>, root: Board, root_node_type: NodeType, fight: bool, limits: &'a SearchLimits) -> Self {
+        Self {
+            table,
+            root,
+            root_progress: (None, None),
+            root_node_type,
+            fight,
+            limits,
+            nodes_expanded: 0,
+            developments: 0
+        }
+    }
+
+    //Numbers of a position, evaluating it if it is not in the table
+    fn numbers(&mut self, board: Board, node_type: NodeType) -> (u32, u32) {
+        if let Some(numbers) = self.table.lookup(board) {
+            return numbers;
+        }
+
+        self.nodes_expanded += 1;
+        let mut node = Node::new(board, node_type);
+        node.evaluate(self.root_node_type, self.fight);
+        node.set_proof_and_disproof_numbers(&[]);
+        self.table.store(board, node.proof, node.disproof);
+        (node.proof, node.disproof)
+    }
+
+    //Searches the position until one of its numbers reaches its threshold, and returns its
+    //numbers with the column of a proved child
+    fn search(&mut self, board: Board, node_type: NodeType, proof_threshold: u32, disproof_threshold: u32,
+              monitor: &mut Monitor) -> (u32, u32, Option<usize>) {
+        self.developments += 1;
+        monitor.report(self.nodes_expanded, || self.root_progress);
+        let child_type = match node_type {
+            NodeType::And => NodeType::Or,
+            NodeType::Or => NodeType::And
+        };
+        let children: [Option<Board>; Board::WIDTH] = array::from_fn(|column| {
+            board.make_move(column).ok().map(|new_board| new_board.min(new_board.symmetric_board()))
+        });
+
+        loop {
+            let mut numbers = [(MAX_VALUE, MAX_VALUE); Board::WIDTH];
+            for column in 0..Board::WIDTH {
+                if let Some(child) = children[column] {
+                    numbers[column] = self.numbers(child, child_type);
+                }
+            }
+            let columns = NODE_SEQUENCE_ORDER.into_iter().filter(|&column| children[column].is_some());
+
+            //The numbers to minimize for this node and to sum for the other ones are
+            //swapped in And nodes
+            let (minimized, summed, threshold, other_threshold) = match node_type {
+                NodeType::Or => (0, 1, proof_threshold, disproof_threshold),
+                NodeType::And => (1, 0, disproof_threshold, proof_threshold)
+            };
+            let get = |numbers: (u32, u32), index| if index == 0 { numbers.0 } else { numbers.1 };
+            let mut best = None;
+            let mut best_number = MAX_VALUE;
+            let mut second_number = MAX_VALUE;
+            let mut sum: u32 = 0;
+            for column in columns {
+                let number = get(numbers[column], minimized);
+                if best.is_none() || number < best_number {
+                    second_number = best_number;
+                    best_number = number;
+                    best = Some(column);
+                } else if number < second_number {
+                    second_number = number;
+                }
+                sum = sum.saturating_add(get(numbers[column], summed)).min(MAX_VALUE);
+            }
+            if best_number == 0 {
+                sum = MAX_VALUE; //Proved Or node, or disproved And node
+            }
+
+            let (proof, disproof) = match node_type {
+                NodeType::Or => (best_number, sum),
+                NodeType::And => (sum, best_number)
+            };
+            if board == self.root {
+                self.root_progress = (best, Some((proof, disproof)));
+            }
+            if proof >= proof_threshold || disproof >= disproof_threshold || self.limits.exceeded(self.nodes_expanded, 0)
+                || monitor.is_cancelled() {
+                self.table.store(board, proof, disproof);
+                let proved_child = if proof == 0 && matches!(node_type, NodeType::Or) {
+                    best
+                } else {
+                    None
+                };
+                return (proof, disproof, proved_child);
+            }
+
+            //The child keeps the focus until it is no more the best one, or until this node
+            //reaches its threshold
+            let column = best.unwrap();
+            let child_threshold = threshold.min(second_number.saturating_add(1));
+            let child_other_threshold = other_threshold.saturating_sub(sum).saturating_add(get(numbers[column], summed)).min(MAX_VALUE);
+            let (child_proof_threshold, child_disproof_threshold) = match node_type {
+                NodeType::Or => (child_threshold, child_other_threshold),
+                NodeType::And => (child_other_threshold, child_threshold)
+            };
+            self.search(children[column].unwrap(), child_type, child_proof_threshold, child_disproof_threshold, monitor);
+        }
+    }
+
+    //Same as the path of the best-first search, following the positions left in the table
+    fn principal_variation(&self, board: Board, node_type: NodeType, best_move: Option<usize>) -> Vec<usize> {
+        let mut variation = Vec::new();
+        let mut board = board;
+        let mut node_type = node_type;
+        let mut mirrored = false;
+        while let Some((proof, disproof)) = self.table.get(board) {
+            let column = NODE_SEQUENCE_ORDER.into_iter().find(|&column| {
+                let Ok(new_board) = board.make_move(column) else {
+                    return false;
+                };
+                let numbers = self.table.get(new_board.min(new_board.symmetric_board()));
+                match node_type {
+                    //The proved move of the root is known even if it is not in the table
+                    NodeType::Or if variation.is_empty() && best_move.is_some() => Some(column) == best_move,
+                    NodeType::Or => numbers.is_some_and(|numbers| numbers.0 == proof),
+                    NodeType::And => numbers.is_some_and(|numbers| numbers.1 == disproof)
+                }
+            });
+            let Some(column) = column else {
+                break;
+            };
+            variation.push(if mirrored {
+                Board::WIDTH - 1 - column
+            } else {
+                column
+            });
+
+            let new_board = board.make_move(column).unwrap();
+            board = new_board.min(new_board.symmetric_board());
+            if board != new_board {
+                mirrored = !mirrored;
+            }
+            node_type = match node_type {
+                NodeType::And => NodeType::Or,
+                NodeType::Or => NodeType::And
+            };
+        }
+        variation
+    }
+}
+
+//The table may hold positions from previous searches
+fn depth_first_proof_number_search(board: Board, fight: bool, limits: &SearchLimits, table: &mut TranspositionTable,
+                                   monitor: &mut Monitor) -> SearchResult {
+    let mut root = Node::new(board, NodeType::Or);
+    let winning_move = root.evaluate(NodeType::Or, fight);
+    if root.value != NodeValue::Unknown {
+        root.set_proof_and_disproof_numbers(&[]);
+        return SearchResult {
+            verdict: root.value.into(),
+            principal_variation: winning_move.into_iter().collect(),
+            proof: root.proof,
+            disproof: root.disproof,
+            nodes: 0,
+            developments: 0,
+            losing_moves: Vec::new()
+        };
+    }
+
+    let table = SharedTable::new(table, board.player_to_play(), fight);
+    let mut search = DepthFirstSearch::new(table, board, NodeType::Or, fight, limits);
+    let (proof, disproof, best_move) = search.search(board, NodeType::Or, MAX_VALUE, MAX_VALUE, monitor);
+    let losing_moves = if fight {
+        (0..Board::WIDTH).filter(|&column| board.make_move(column).is_ok_and(|new_board| {
+            search.table.get(new_board.min(new_board.symmetric_board())).is_some_and(|(_, disproof)| disproof == 0)
+        })).collect()
+    } else {
+        Vec::new()
+    };
+    SearchResult {
+        verdict: Verdict::from_numbers(proof, disproof),
+        principal_variation: search.principal_variation(board, NodeType::Or, best_move),
+        proof,
+        disproof,
+        nodes: search.nodes_expanded,
+        developments: search.developments,
+        losing_moves
+    }
+}
+
+//Searches the position after a move of the root, whose player is the root player
+fn search_child(board: Board, fight: bool, limits: &SearchLimits, algorithm: Algorithm, monitor: &mut Monitor) -> SearchResult {
+    match algorithm {
+        Algorithm::ProofNumber | Algorithm::TwoLevel => {
+            let second_level = match algorithm {
+                Algorithm::TwoLevel => Some(limits),
+                _ => None
+            };
+            let mut tree = Tree::new(Node::new(board, NodeType::And));
+            heuristic_proof_number_search(&mut tree, NodeType::Or, fight, limits, second_level, None, monitor)
+        }
+        Algorithm::DepthFirst => {
+            let mut table = TranspositionTable::new(limits.memory.unwrap_or(TranspositionTable::DEFAULT_MEMORY), Replacement::default());
+            let table = SharedTable::new(&mut table, board.player_to_play().opponent(), fight);
+            let mut search = DepthFirstSearch::new(table, board, NodeType::Or, fight, limits);
+            let (mut proof, mut disproof) = search.numbers(board, NodeType::And);
+            if proof != 0 && disproof != 0 {
+                (proof, disproof, _) = search.search(board, NodeType::And, MAX_VALUE, MAX_VALUE, monitor);
+            }
+            SearchResult {
+                verdict: Verdict::from_numbers(proof, disproof),
+                principal_variation: search.principal_variation(board, NodeType::And, None),
+                proof,
+                disproof,
+                nodes: search.nodes_expanded,
+                developments: search.developments,
+                losing_moves: Vec::new()
+            }
+        }
+    }
+}
+
+//Numbers of the root from the ones of the children searched so far, like in the serial
+//search. The most proving child comes first in the order of the serial search. The moves
+//never searched count as unknown children, so the root is only disproved when every move
+//was searched and disproved.
+fn combine_children(root: Node, columns: &[usize], results: &[(usize, SearchResult)]) -> SearchResult {
+    let mut results: Vec<&(usize, SearchResult)> = results.iter().collect();
+    results.sort_by_key(|&&(column, _)| NODE_SEQUENCE_ORDER.iter().position(|&other| other == column));
+    let Some(&&(first_column, _)) = results.first() else {
+        //Stopped before any child was searched
+        return SearchResult {
+            verdict: Verdict::Unknown,
+            principal_variation: Vec::new(),
+            proof: root.proof,
+            disproof: root.disproof,
+            nodes: 0,
+            developments: 0,
+            losing_moves: Vec::new()
+        };
+    };
+
+    let mut proof = MAX_VALUE;
+    let mut disproof: u32 = 0;
+    let mut best = (first_column, &results[0].1);
+    for (column, result) in results.iter().map(|(column, result)| (*column, result)) {
+        if result.proof < proof {
+            proof = result.proof;
+            best = (column, result);
+        }
+        disproof = disproof.saturating_add(result.disproof);
+    }
+    let unsearched = columns.iter().filter(|&&column| results.iter().all(|&&(searched, _)| searched != column)).count();
+    disproof = disproof.saturating_add(unsearched as u32);
+    if proof == 0 {
+        disproof = MAX_VALUE;
+    }
+
+    let mut principal_variation = vec![best.0];
+    principal_variation.extend_from_slice(&best.1.principal_variation);
+    SearchResult {
+        verdict: Verdict::from_numbers(proof, disproof),
+        principal_variation,
+        proof,
+        disproof: disproof.min(MAX_VALUE),
+        nodes: results.iter().map(|(_, result)| result.nodes).sum(),
+        developments: results.iter().map(|(_, result)| result.developments).sum(),
+        losing_moves: Vec::new()
+    }
+}
+
+//Root splitting: the threads take the moves of the root one after the other. The moves
+//share the node budget, and the calling thread stops every search once the budget is spent
+//or a move is proved, since the root is then proved too. The root gets its numbers from
+//its children like in the serial search, so a proof or a disproof means the same thing. The
+//progress is reported by the calling thread, the root numbers only from the moves searched
+//so far.
+fn parallel_search(board: Board, fight: bool, limits: &SearchLimits, algorithm: Algorithm, monitor: &mut Monitor) -> SearchResult {
+    let mut root = Node::new(board, NodeType::Or);
+    let winning_move = root.evaluate(NodeType::Or, fight);
+    root.set_proof_and_disproof_numbers(&[]);
+    if root.value != NodeValue::Unknown {
+        return SearchResult {
+            verdict: root.value.into(),
+            principal_variation: winning_move.into_iter().collect(),
+            proof: root.proof,
+            disproof: root.disproof,
+            nodes: 0,
+            developments: 0,
+            losing_moves: Vec::new()
+        };
+    }
+
+    //The mirrored moves of a symmetrical board lead to the same position
+    let self_symmetric = board.symmetric_board() == board;
+    let columns: Vec<usize> = NODE_SEQUENCE_ORDER.into_iter()
+        .filter(|&column| board.can_play(column) && !(self_symmetric && Board::WIDTH - 1 - column < column))
+        .collect();
+    let threads = limits.threads.min(columns.len());
+    let child_limits = SearchLimits {
+        memory: limits.memory.map(|memory| memory / threads),
+        threads: 1,
+        ..*limits
+    };
+
+    //Cancelled by the calling thread, and not by the caller of the search
+    let stop = CancellationToken::new();
+    let next = AtomicUsize::new(0);
+    let proved = AtomicBool::new(false);
+    let finished = AtomicUsize::new(0);
+    let nodes = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                while !stop.is_cancelled() {
+                    let Some(&column) = columns.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    let mut child_monitor = Monitor::counting(stop.clone(), &nodes);
+                    let result = search_child(board.make_move(column).unwrap(), fight, &child_limits, algorithm, &mut child_monitor);
+                    if result.verdict == Verdict::Proved {
+                        proved.store(true, Ordering::Relaxed);
+                    }
+                    results.lock().unwrap().push((column, result));
+                }
+                finished.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+
+        while finished.load(Ordering::Relaxed) < threads {
+            thread::sleep(Duration::from_millis(10));
+            if proved.load(Ordering::Relaxed) || nodes.load(Ordering::Relaxed) > limits.nodes || monitor.is_cancelled() {
+                stop.cancel();
+            }
+            monitor.report(nodes.load(Ordering::Relaxed), || {
+                let result = combine_children(root, &columns, &results.lock().unwrap());
+                (result.best_move(), Some((result.proof, result.disproof)))
+            });
+        }
+    });
+
+    let results = results.into_inner().unwrap();
+    let mut result = combine_children(root, &columns, &results);
+    if fight {
+        for (column, child) in results.iter() {
+            if child.disproof == 0 {
+                result.losing_moves.push(*column);
+                if self_symmetric && *column != Board::WIDTH - 1 - column {
+                    result.losing_moves.push(Board::WIDTH - 1 - column);
+                }
+            }
+        }
+        result.losing_moves.sort();
+    }
+    result
 }
 
-pub fn heuristic_best_play(board: Board, fight: bool) -> Option<usize> {
+//Searches the canonical orientation of the board, the columns of the result are then
+//mirrored back if needed. The tree of the previous search is reused when the board is in it,
+//and the parallel search leaves it untouched.
+fn search_canonical(board: Board, fight: bool, limits: &SearchLimits, algorithm: Algorithm, tree: Option<Tree>,
+                    table: &mut TranspositionTable, monitor: &mut Monitor) -> (SearchResult, Option<Tree>) {
     let symmetric = board.symmetric_board();
     let is_symmetric = symmetric < board;
     let node_board = if is_symmetric {
@@ -316,23 +1110,556 @@ pub fn heuristic_best_play(board: Board, fight: bool) -> Option<usize> {
     } else {
         board
     };
-    let key = node_board.key();
-    let root_node = Rc::new(RefCell::new(
-        Node::new(node_board, NodeType::Or)
-    ));
-    
-    let mut nodes = HashMap::new();
-    nodes.insert(key, root_node.clone());
-    let best_move = heuristic_proof_number_search(root_node.clone(), &mut nodes, fight);
+    monitor.set_mirrored(is_symmetric);
+    let (mut result, tree) = match algorithm {
+        _ if limits.threads > 1 => (parallel_search(node_board, fight, limits, algorithm, monitor), tree),
+        Algorithm::ProofNumber | Algorithm::TwoLevel => {
+            let second_level = match algorithm {
+                Algorithm::TwoLevel => Some(limits),
+                _ => None
+            };
+            let reused = tree.and_then(|tree| tree.reroot(node_board));
+            let mut tree = reused.unwrap_or_else(|| Tree::new(Node::new(node_board, NodeType::Or)));
+            let mut table = SharedTable::new(table, node_board.player_to_play(), fight);
+            let mut result = heuristic_proof_number_search(&mut tree, NodeType::Or, fight, limits, second_level, Some(&mut table), monitor);
+            tree.store_expanded(&mut table);
+            if fight {
+                let children = tree.nodes[0].child;
+                result.losing_moves = (0..Board::WIDTH)
+                    .filter(|&column| children[column] != NO_NODE && tree.nodes[children[column] as usize].disproof == 0)
+                    .collect();
+            }
+            (result, Some(tree))
+        }
+        Algorithm::DepthFirst => (depth_first_proof_number_search(node_board, fight, limits, table, monitor), None)
+    };
     
-    let root_node_borrow = root_node.borrow();
-    match root_node_borrow.deref().value {
-        NodeValue::Disproved => None,
-        NodeValue::Unknown => None,
-        NodeValue::Proved => Some(if is_symmetric {
-            Board::WIDTH - 1 - best_move.unwrap()
-        } else {
-            best_move.unwrap()
-        })
+    if is_symmetric {
+        for column in result.principal_variation.iter_mut().chain(result.losing_moves.iter_mut()) {
+            *column = Board::WIDTH - 1 - *column;
+        }
+        result.losing_moves.reverse();
+    }
+    (result, tree)
+}
+
+//The tree is the one of the last search, which is the search of a draw when AvoidLoss does
+//not find a win. The depth-first and the parallel searches do not keep any tree.
+pub fn heuristic_search_with_tree(board: Board, goal: Goal, limits: &SearchLimits, algorithm: Algorithm) -> (SearchResult, Option<ProofTree>) {
+    let mut session = SearchSession::for_one_search(goal, *limits, algorithm);
+    let result = session.search(board);
+    let tree = session.trees.into_iter().rev().flatten().next().map(ProofTree);
+    (result, tree)
+}
+
+pub fn heuristic_search(board: Board, goal: Goal, limits: &SearchLimits, algorithm: Algorithm) -> SearchResult {
+    SearchSession::for_one_search(goal, *limits, algorithm).search(board)
+}
+
+//A cancelled search returns what it found so far: the verdict is usually unknown, and the
+//best move is the most proving one
+pub fn heuristic_search_with_control(board: Board, goal: Goal, limits: &SearchLimits, algorithm: Algorithm,
+                                     control: &mut SearchControl) -> SearchResult {
+    SearchSession::for_one_search(goal, *limits, algorithm).search_with_control(board, control)
+}
+
+//Keeps the trees and the transposition table between the searches of a game, so that the
+//next search starts from what was learned about the new position. The limits apply to each
+//search separately, except for the memory. The table is shared by the searches of a win
+//and of at least a draw, and by the players. With the best-first searches, the memory limit
+//covers both the table and the tree.
+pub struct SearchSession {
+    goal: Goal,
+    limits: SearchLimits,
+    algorithm: Algorithm,
+    trees: [Option<Tree>; 2], //For the searches of a win and of at least a draw
+    table: TranspositionTable
+}
+
+impl SearchSession {
+    //The depth-first search only has its table, so it gets the whole memory limit. The other
+    //ones give a quarter of it to the table and keep the rest for the tree. Without a limit,
+    //the table gets the default size.
+    pub fn new(goal: Goal, limits: SearchLimits, algorithm: Algorithm) -> Self {
+        let memory = match algorithm {
+            Algorithm::DepthFirst => limits.memory.unwrap_or(TranspositionTable::DEFAULT_MEMORY),
+            Algorithm::ProofNumber | Algorithm::TwoLevel => limits.memory.map_or(TranspositionTable::DEFAULT_MEMORY, |memory| memory / 4)
+        };
+        Self::with_table(goal, limits, algorithm, TranspositionTable::new(memory, Replacement::default()))
+    }
+
+    //For the searches which do not keep anything. A single best-first search never finds in
+    //the table what is not already in its tree, so it only gets a pair of entries.
+    pub(crate) fn for_one_search(goal: Goal, limits: SearchLimits, algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::DepthFirst => Self::new(goal, limits, algorithm),
+            Algorithm::ProofNumber | Algorithm::TwoLevel => Self::with_table(goal, limits, algorithm, TranspositionTable::new(0, Replacement::default()))
+        }
+    }
+
+    pub fn with_table(goal: Goal, limits: SearchLimits, algorithm: Algorithm, table: TranspositionTable) -> Self {
+        Self {
+            goal,
+            limits,
+            algorithm,
+            trees: [None, None],
+            table
+        }
+    }
+
+    pub fn limits(&self) -> SearchLimits {
+        self.limits
+    }
+
+    //For example to give a deadline to the next search
+    pub fn set_limits(&mut self, limits: SearchLimits) {
+        self.limits = limits;
+    }
+
+    pub fn table(&self) -> &TranspositionTable {
+        &self.table
+    }
+
+    //To clear it, resize it or change its replacement policy
+    pub fn table_mut(&mut self) -> &mut TranspositionTable {
+        &mut self.table
+    }
+
+    //The table is kept, since its positions may appear in the next games
+    pub fn forget_trees(&mut self) {
+        self.trees = [None, None];
+    }
+
+    //Forgets everything, for example to free the memory between two games
+    pub fn clear(&mut self) {
+        self.forget_trees();
+        self.table.clear();
+    }
+
+    fn search_with(&mut self, board: Board, fight: bool, monitor: &mut Monitor) -> SearchResult {
+        //The tree of the best-first searches gets what the table leaves
+        let limits = match self.algorithm {
+            Algorithm::DepthFirst => self.limits,
+            Algorithm::ProofNumber | Algorithm::TwoLevel => SearchLimits {
+                memory: self.limits.memory.map(|memory| memory.saturating_sub(self.table.memory())),
+                ..self.limits
+            }
+        };
+        let tree = &mut self.trees[fight as usize];
+        let (mut result, new_tree) = search_canonical(board, fight, &limits, self.algorithm, tree.take(), &mut self.table, monitor);
+        *tree = new_tree;
+
+        //A stopped search may leave a lost move as the most proving one, when the other moves
+        //were not searched yet
+        if result.verdict == Verdict::Unknown && result.best_move().is_some_and(|column| result.losing_moves.contains(&column)) {
+            if let Some(column) = (0..Board::WIDTH).find(|&column| board.can_play(column) && !result.losing_moves.contains(&column)) {
+                result.principal_variation = vec![column];
+            }
+        }
+        result
+    }
+
+    pub fn search(&mut self, board: Board) -> SearchResult {
+        self.search_with_control(board, &mut SearchControl::default())
+    }
+
+    pub fn search_with_control(&mut self, board: Board, control: &mut SearchControl) -> SearchResult {
+        let mut monitor = Monitor::new(control);
+        match self.goal {
+            Goal::Win => self.search_with(board, false, &mut monitor),
+            Goal::AtLeastDraw => self.search_with(board, true, &mut monitor),
+            Goal::AvoidLoss => {
+                let win = self.search_with(board, false, &mut monitor);
+                if win.verdict == Verdict::Proved || monitor.is_cancelled() {
+                    return win;
+                }
+                monitor.add_previous_nodes(win.nodes);
+                let mut draw = self.search_with(board, true, &mut monitor);
+                draw.nodes += win.nodes;
+                draw.developments += win.developments;
+                draw
+            }
+        }
+    }
+}
+
+//The move proving the root, if any
+pub fn heuristic_best_play(board: Board, goal: Goal, limits: &SearchLimits, algorithm: Algorithm) -> Option<usize> {
+    let result = heuristic_search(board, goal, limits, algorithm);
+    match result.verdict() {
+        Verdict::Proved => result.best_move(),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::testing::{play, RandomGames};
+    use crate::control::Progress;
+    use crate::solver::{Score, Solver};
+
+    //The move reaches the goal according to the solver
+    fn reaches(board: Board, column: usize, goal: Goal, solver: &mut Solver) -> bool {
+        let score = solver.solve(board.make_move(column).unwrap()).parent();
+        match goal {
+            Goal::Win => matches!(score, Score::Win(_)),
+            _ => score >= Score::Draw
+        }
+    }
+
+    //Both searches prove the same positions. They may prove them through different moves, but
+    //when only one move reaches the goal, both have to find it.
+    #[test]
+    fn depth_first_agrees_with_best_first() {
+        let limits = SearchLimits {
+            nodes: 3000,
+            ..SearchLimits::default()
+        };
+        let mut solver = Solver::new();
+        let mut games = RandomGames::new(2024);
+        let mut compared = 0;
+        for game in 0..60 {
+            let Some(board) = games.position(22 + game % 10) else {
+                continue;
+            };
+            for goal in [Goal::Win, Goal::AtLeastDraw] {
+                let best_first = heuristic_search(board, goal, &limits, Algorithm::ProofNumber);
+                let depth_first = heuristic_search(board, goal, &limits, Algorithm::DepthFirst);
+                if best_first.verdict() == Verdict::Unknown || depth_first.verdict() == Verdict::Unknown {
+                    continue;
+                }
+                compared += 1;
+                assert_eq!(best_first.verdict(), depth_first.verdict(), "{board:?} {goal:?}");
+                if best_first.verdict() != Verdict::Proved {
+                    continue;
+                }
+                let best_first_move = best_first.best_move().unwrap();
+                let depth_first_move = depth_first.best_move().unwrap();
+                assert!(reaches(board, best_first_move, goal, &mut solver), "{board:?} {goal:?}");
+                assert!(reaches(board, depth_first_move, goal, &mut solver), "{board:?} {goal:?}");
+                let moves = (0..Board::WIDTH)
+                    .filter(|&column| board.can_play(column) && reaches(board, column, goal, &mut solver))
+                    .count();
+                if moves == 1 {
+                    assert_eq!(best_first_move, depth_first_move, "{board:?} {goal:?}");
+                }
+            }
+        }
+        assert!(compared >= 20, "only {compared} positions compared");
+    }
+
+    //The moves proved to lose do lose, and the best move is not one of them when another
+    //move draws, with both algorithms
+    #[test]
+    fn avoid_loss_keeps_away_from_lost_moves() {
+        let limits = SearchLimits {
+            nodes: 300,
+            ..SearchLimits::default()
+        };
+        let mut solver = Solver::new();
+        let mut games = RandomGames::new(7);
+        let mut found = 0;
+        for game in 0..60 {
+            let Some(board) = games.position(16 + game % 12) else {
+                continue;
+            };
+            let can_draw = (0..Board::WIDTH).any(|column| board.can_play(column) && reaches(board, column, Goal::AtLeastDraw, &mut solver));
+            for algorithm in [Algorithm::ProofNumber, Algorithm::DepthFirst] {
+                let result = heuristic_search(board, Goal::AvoidLoss, &limits, algorithm);
+                for &column in result.losing_moves() {
+                    assert!(!reaches(board, column, Goal::AtLeastDraw, &mut solver), "{board:?} {column}");
+                    found += 1;
+                }
+                if can_draw && result.verdict() != Verdict::Disproved {
+                    let best_move = result.best_move().unwrap();
+                    assert!(!result.losing_moves().contains(&best_move), "{board:?} {algorithm:?}");
+                }
+            }
+        }
+        assert!(found >= 20, "only {found} losing moves found");
+    }
+
+    //The second level searches count in the budget, and PN² proves what PN proves
+    #[test]
+    fn two_level_search_keeps_to_its_budget() {
+        let limits = SearchLimits {
+            nodes: 2000,
+            ..SearchLimits::default()
+        };
+        let result = heuristic_search(play(&[3]), Goal::Win, &limits, Algorithm::TwoLevel);
+        assert_eq!(result.verdict(), Verdict::Unknown);
+        assert!(result.nodes() <= limits.nodes + Board::WIDTH, "{} nodes", result.nodes());
+
+        let mut solver = Solver::new();
+        let mut games = RandomGames::new(99);
+        let mut compared = 0;
+        for game in 0..60 {
+            let Some(board) = games.position(20 + game % 12) else {
+                continue;
+            };
+            for goal in [Goal::Win, Goal::AtLeastDraw] {
+                let two_level = heuristic_search(board, goal, &limits, Algorithm::TwoLevel);
+                assert!(two_level.nodes() <= limits.nodes + Board::WIDTH, "{board:?} {goal:?}");
+                if two_level.verdict() == Verdict::Unknown {
+                    continue;
+                }
+                let best_first = heuristic_search(board, goal, &limits, Algorithm::ProofNumber);
+                if best_first.verdict() != Verdict::Unknown {
+                    assert_eq!(two_level.verdict(), best_first.verdict(), "{board:?} {goal:?}");
+                }
+                compared += 1;
+                let reached = (0..Board::WIDTH).any(|column| board.can_play(column) && reaches(board, column, goal, &mut solver));
+                assert_eq!(two_level.verdict() == Verdict::Proved, reached, "{board:?} {goal:?}");
+                if two_level.verdict() == Verdict::Proved {
+                    assert!(reaches(board, two_level.best_move().unwrap(), goal, &mut solver), "{board:?} {goal:?}");
+                }
+            }
+        }
+        assert!(compared >= 20, "only {compared} positions compared");
+    }
+
+    //The searches stop soon after the token is cancelled, even with a budget they would take
+    //minutes to spend
+    #[test]
+    fn cancelled_searches_stop() {
+        let limits = SearchLimits {
+            nodes: 10_000_000,
+            ..SearchLimits::default()
+        };
+        for algorithm in [Algorithm::ProofNumber, Algorithm::DepthFirst, Algorithm::TwoLevel] {
+            let token = CancellationToken::new();
+            let canceller = token.clone();
+            let start = Instant::now();
+            let result = thread::scope(|scope| {
+                scope.spawn(move || {
+                    thread::sleep(Duration::from_millis(200));
+                    canceller.cancel();
+                });
+                heuristic_search_with_control(play(&[3]), Goal::Win, &limits, algorithm, &mut SearchControl::new(token))
+            });
+            assert_eq!(result.verdict(), Verdict::Unknown, "{algorithm:?}");
+            assert!(start.elapsed() < Duration::from_secs(5), "{algorithm:?} took {:?}", start.elapsed());
+        }
     }
-}
\ No newline at end of file
+
+    //The search of a board and of its mirror go through the same canonical board, and each
+    //reports the moves seen from its own board
+    #[test]
+    fn progress_is_reported_in_the_orientation_of_the_board() {
+        let limits = SearchLimits {
+            nodes: 500,
+            ..SearchLimits::default()
+        };
+        let [left, right] = [play(&[0, 3]), play(&[6, 3])].map(|board| {
+            let mut moves = Vec::new();
+            let mut control = SearchControl::with_progress(CancellationToken::new(), |progress: &Progress| moves.extend(progress.best_move()));
+            control.interval = Duration::ZERO;
+            let result = heuristic_search_with_control(board, Goal::Win, &limits, Algorithm::ProofNumber, &mut control);
+            drop(control);
+            assert_eq!(moves.last().copied(), result.best_move());
+            moves
+        });
+        assert!(!left.is_empty());
+        assert!(left.iter().any(|&column| column != 3));
+        assert_eq!(left, right.iter().map(|&column| Board::WIDTH - 1 - column).collect::<Vec<_>>());
+    }
+
+    //A stopped parallel search may not have searched every move: the root is not disproved
+    //by the moves which were
+    #[test]
+    fn unsearched_moves_are_unknown() {
+        let board = play(&[3]);
+        let mut root = Node::new(board, NodeType::Or);
+        root.evaluate(NodeType::Or, false);
+        let disproved = |column| (column, SearchResult {
+            verdict: Verdict::Disproved,
+            principal_variation: Vec::new(),
+            proof: MAX_VALUE,
+            disproof: 0,
+            nodes: 1,
+            developments: 1,
+            losing_moves: Vec::new()
+        });
+        let columns = [3, 2, 4];
+        let some = [disproved(3), disproved(4)];
+        assert_eq!(combine_children(root, &columns, &some).verdict(), Verdict::Unknown);
+        let all = [disproved(3), disproved(4), disproved(2)];
+        assert_eq!(combine_children(root, &columns, &all).verdict(), Verdict::Disproved);
+    }
+
+    //Just enough JSON for the export of the trees: no escapes, no floating point numbers
+    #[derive(Debug, PartialEq)]
+    enum Json {
+        Bool(bool),
+        Number(u64),
+        String(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>)
+    }
+
+    impl Json {
+        fn parse(text: &str) -> Option<Json> {
+            let mut rest = text.as_bytes();
+            let value = Self::value(&mut rest)?;
+            rest.is_empty().then_some(value)
+        }
+
+        fn value(rest: &mut &[u8]) -> Option<Json> {
+            let (&first, tail) = rest.split_first()?;
+            match first {
+                b'"' => {
+                    let end = tail.iter().position(|&c| c == b'"')?;
+                    let string = String::from_utf8(tail[..end].to_vec()).ok()?;
+                    *rest = &tail[end + 1..];
+                    Some(Json::String(string))
+                }
+                b'[' | b'{' => {
+                    *rest = tail;
+                    let close = if first == b'[' { b']' } else { b'}' };
+                    let mut items = Vec::new();
+                    let mut fields = Vec::new();
+                    if rest.first() == Some(&close) {
+                        *rest = &rest[1..];
+                    } else {
+                        loop {
+                            if first == b'{' {
+                                let Json::String(key) = Self::value(rest)? else {
+                                    return None;
+                                };
+                                *rest = rest.strip_prefix(b":")?;
+                                fields.push((key, Self::value(rest)?));
+                            } else {
+                                items.push(Self::value(rest)?);
+                            }
+                            let (&separator, tail) = rest.split_first()?;
+                            *rest = tail;
+                            if separator == close {
+                                break;
+                            }
+                            if separator != b',' {
+                                return None;
+                            }
+                        }
+                    }
+                    Some(if first == b'[' { Json::Array(items) } else { Json::Object(fields) })
+                }
+                b't' | b'f' => {
+                    let (word, value) = if first == b't' { (&b"true"[..], true) } else { (&b"false"[..], false) };
+                    *rest = rest.strip_prefix(word)?;
+                    Some(Json::Bool(value))
+                }
+                _ => {
+                    let end = rest.iter().position(|c| !c.is_ascii_digit()).unwrap_or(rest.len());
+                    let number = std::str::from_utf8(&rest[..end]).ok()?.parse().ok()?;
+                    *rest = &rest[end..];
+                    Some(Json::Number(number))
+                }
+            }
+        }
+
+        fn field(&self, name: &str) -> &Json {
+            let Json::Object(fields) = self else {
+                panic!("{self:?} is not an object");
+            };
+            &fields.iter().find(|(key, _)| key == name).unwrap().1
+        }
+
+        fn items(&self) -> &[Json] {
+            let Json::Array(items) = self else {
+                panic!("{self:?} is not an array");
+            };
+            items
+        }
+    }
+
+    //Both exports hold every node and every edge, and flag the principal variation
+    #[test]
+    fn proof_tree_exports() {
+        let limits = SearchLimits {
+            nodes: 200,
+            ..SearchLimits::default()
+        };
+        let (result, tree) = heuristic_search_with_tree(play(&[3, 3]), Goal::Win, &limits, Algorithm::ProofNumber);
+        let tree = tree.unwrap();
+        let edges = tree.0.nodes.iter().flat_map(|node| node.child).filter(|&child| child != NO_NODE).count();
+
+        let dot = tree.to_dot();
+        assert!(dot.starts_with("digraph proof_tree {") && dot.ends_with("}\n"));
+        assert_eq!(dot.lines().filter(|line| line.contains(" [label=") && !line.contains("->")).count(), tree.len());
+        assert_eq!(dot.lines().filter(|line| line.contains("->")).count(), edges);
+
+        let json = Json::parse(&tree.to_json()).expect("malformed JSON");
+        let nodes = json.field("nodes").items();
+        assert_eq!(nodes.len(), tree.len());
+        assert_eq!(nodes.iter().map(|node| node.field("children").items().len()).sum::<usize>(), edges);
+        for (id, node) in nodes.iter().enumerate() {
+            assert_eq!(node.field("id"), &Json::Number(id as u64));
+            assert_eq!(node.field("board"), &Json::String(tree.0.nodes[id].board.to_string()));
+        }
+
+        //The flagged nodes are the ones reached by the principal variation
+        let most_proving = |id: NodeId| nodes[id as usize].field("most_proving") == &Json::Bool(true);
+        assert!(result.principal_variation().len() >= 2);
+        let mut node: NodeId = 0;
+        let mut mirrored = false;
+        assert!(most_proving(node));
+        for &column in result.principal_variation() {
+            let column = if mirrored { Board::WIDTH - 1 - column } else { column };
+            let child = tree.0.nodes[node as usize].child[column];
+            assert!(most_proving(child), "{column}");
+            if tree.0.nodes[node as usize].board.make_move(column).unwrap() != tree.0.nodes[child as usize].board {
+                mirrored = !mirrored;
+            }
+            node = child;
+        }
+        let flagged = (0..tree.len() as NodeId).filter(|&id| most_proving(id)).count();
+        assert_eq!(flagged, result.principal_variation().len() + 1);
+    }
+
+    #[test]
+    fn parallel_search_shares_the_budget() {
+        let limits = SearchLimits {
+            nodes: 1000,
+            threads: 3,
+            ..SearchLimits::default()
+        };
+        let result = heuristic_search(Board::new().make_move(3).unwrap(), Goal::Win, &limits, Algorithm::ProofNumber);
+        assert_eq!(result.verdict(), Verdict::Unknown);
+        //The searches are stopped by a thread checking the nodes from time to time
+        assert!(result.nodes() < 2 * limits.nodes, "{} nodes", result.nodes());
+    }
+
+    //The numbers of every node of a rerooted tree are the ones it had in the old tree, and
+    //still follow from the numbers of its children
+    #[test]
+    fn reroot_keeps_the_numbers() {
+        let limits = SearchLimits {
+            nodes: 2000,
+            ..SearchLimits::default()
+        };
+        let board = play(&[3, 3, 2, 4, 2, 2, 4, 4]);
+        let mut tree = Tree::new(Node::new(board, NodeType::Or));
+        heuristic_proof_number_search(&mut tree, NodeType::Or, false, &limits, None, None, &mut Monitor::silent(CancellationToken::new()));
+
+        //The most proving expanded grandchild of the root
+        let child = tree.nodes[0].child[tree.most_proving_child(0)];
+        let grandchild = tree.nodes[child as usize].child[tree.most_proving_child(child)];
+        let new_root = tree.nodes[grandchild as usize];
+        assert_eq!(new_root.state, NodeState::Expanded);
+
+        let old_numbers: HashMap<u64, (u32, u32)> = tree.nodes.iter().map(|node| (node.board.key(), (node.proof, node.disproof))).collect();
+        let rerooted = tree.reroot(new_root.board).unwrap();
+        assert_eq!((rerooted.nodes[0].proof, rerooted.nodes[0].disproof), (new_root.proof, new_root.disproof));
+        for (id, node) in rerooted.nodes.iter().enumerate() {
+            assert_eq!(old_numbers[&node.board.key()], (node.proof, node.disproof));
+            assert_eq!(rerooted.table[&node.board.key()], id as NodeId);
+            assert!(id == 0 || node.first_parent != NO_NODE);
+            if node.state == NodeState::Expanded {
+                let mut updated = *node;
+                updated.set_proof_and_disproof_numbers(&rerooted.nodes);
+                assert_eq!((updated.proof, updated.disproof), (node.proof, node.disproof));
+            }
+        }
+    }
+}
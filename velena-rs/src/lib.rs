@@ -1,5 +1,12 @@
 pub mod board;
 pub mod ai;
-mod groups;
-mod book;
-mod heuristic;
\ No newline at end of file
+pub mod groups;
+pub mod rules;
+pub mod strategy;
+pub mod analysis;
+pub mod solver;
+pub mod heuristic;
+pub mod control;
+pub mod table;
+pub mod fallback;
+mod book;
\ No newline at end of file
@@ -0,0 +1,618 @@
+/*
+ rules.c
+*/
+
+//Allis' rules, seen from the player controlling zugzwang. Every rule gives him a set
+//of squares he is guaranteed to get (fully or partially) by answering the moves of his
+//opponent in a specific way, which refutes some of the groups of his opponent.
+//Rows are counted from 1 in the rule definitions, as Allis does: the bottom row is odd.
+
+use std::fmt;
+use crate::board::{Board, Player, SquareSet};
+use crate::groups::{GroupSet, GROUP_MASKS};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Rule {
+    Claimeven,
+    Baseinverse,
+    Vertical,
+    Aftereven,
+    Lowinverse,
+    Highinverse,
+    Baseclaim,
+    Before,
+    Specialbefore
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct RuleInstance {
+    rule: Rule,
+    squares: SquareSet,
+    claimevens: SquareSet,
+    solutions: GroupSet
+}
+
+impl RuleInstance {
+    fn new(rule: Rule, squares: u64, claimevens: u64, solutions: GroupSet) -> Self {
+        Self {
+            rule,
+            squares: SquareSet::from_mask(squares),
+            claimevens: SquareSet::from_mask(claimevens),
+            solutions
+        }
+    }
+
+    pub fn rule(self) -> Rule {
+        self.rule
+    }
+
+    //Squares used by the controller of zugzwang to apply the rule
+    pub fn squares(self) -> SquareSet {
+        self.squares
+    }
+
+    //Squares of the Claimevens the rule relies on. Those can be shared with other
+    //rules relying on the very same Claimevens.
+    pub fn claimevens(self) -> SquareSet {
+        self.claimevens
+    }
+
+    //Groups of the opponent refuted by the rule
+    pub fn solutions(self) -> GroupSet {
+        self.solutions
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl fmt::Display for RuleInstance {
+    //For example "Claimeven on c1/c2 refutes a2-d2, c2-f2"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} on {}", self.rule, self.squares)?;
+        if self.rule != Rule::Claimeven && !self.claimevens.is_empty() {
+            write!(f, " (using the Claimevens on {})", self.claimevens)?;
+        }
+        if self.solutions.is_empty() {
+            write!(f, " refutes nothing but pairs squares")
+        } else {
+            write!(f, " refutes {}", self.solutions)
+        }
+    }
+}
+
+//Squares of the even rows (2, 4, 6)
+pub(crate) const EVEN_ROWS: u64 = {
+    let mut mask = 0;
+    let mut column = 0;
+    while column < Board::WIDTH {
+        let mut row = 1;
+        while row < Board::HEIGHT {
+            mask |= 1 << (column * (Board::HEIGHT + 1) + row);
+            row += 2;
+        }
+        column += 1;
+    }
+    mask
+};
+
+pub(crate) struct Context {
+    opponent: u64,
+    empty: u64,
+    playable: u64,
+    even: u64,
+    threats: GroupSet //Groups the opponent can still complete
+}
+
+impl Context {
+    pub(crate) fn new(board: Board, controller: Player) -> Self {
+        let own = board.player_positions(controller);
+        let mut threats = GroupSet::EMPTY;
+        for (group, mask) in GROUP_MASKS.iter().enumerate() {
+            if mask & own == 0 {
+                threats = threats.union(GroupSet::single(group));
+            }
+        }
+
+        Self {
+            opponent: board.player_positions(controller.opponent()),
+            empty: board.empty_positions(),
+            playable: board.playable_positions(),
+            even: EVEN_ROWS,
+            threats
+        }
+    }
+
+    //White controls zugzwang thanks to an odd threat. Below the threat, the parity of its
+    //column is shifted so that White claims the threat square. The squares above the
+    //threat do not matter anymore, since White wins as soon as the threat is reached.
+    pub(crate) fn with_odd_threat(board: Board, threat: u64) -> Self {
+        let mut context = Self::new(board, Player::White);
+        let column = Board::column_mask(column_of(threat));
+        let above = above(threat);
+
+        //White cannot use those squares in his own groups either
+        context.empty &= !above;
+        context.opponent |= above;
+        context.even = (context.even & !column) | (column & !EVEN_ROWS & !above);
+        for (group, mask) in GROUP_MASKS.iter().enumerate() {
+            if mask & above != 0 {
+                context.threats = context.threats.difference(GroupSet::single(group));
+            }
+        }
+        context
+    }
+
+    //The Claimeven giving the threat square to White. It is needed even if it does not
+    //refute any group.
+    pub(crate) fn threat_claimeven(&self, threat: u64) -> RuleInstance {
+        let squares = threat | threat >> 1;
+        RuleInstance::new(Rule::Claimeven, squares, squares, self.refuted(threat))
+    }
+
+    pub(crate) fn threats(&self) -> GroupSet {
+        self.threats
+    }
+
+    pub(crate) fn empty(&self) -> u64 {
+        self.empty
+    }
+
+    //Squares of the given set which cannot be paired into Claimevens within the set. The
+    //controller answers moves outside of the rules by following up in the same column,
+    //which is impossible on those squares.
+    pub(crate) fn unpaired(&self, squares: u64) -> u64 {
+        let uppers = squares & self.even & (squares << 1);
+        squares & !(uppers | uppers >> 1)
+    }
+
+    //Baseinverses and Verticals which refute nothing, but may still be needed to pair
+    //squares which cannot be followed up
+    pub(crate) fn pairings(&self) -> Vec<RuleInstance> {
+        let mut output = Vec::new();
+        let playable: Vec<u64> = bits(self.playable).collect();
+        for (i, &first) in playable.iter().enumerate() {
+            for &second in &playable[i + 1..] {
+                if self.refuted(first | second).is_empty() {
+                    output.push(RuleInstance::new(Rule::Baseinverse, first | second, 0, GroupSet::EMPTY));
+                }
+            }
+        }
+        for upper in bits(!self.even & self.empty & (self.empty << 1)) {
+            let squares = upper | upper >> 1;
+            if self.refuted(squares).is_empty() {
+                output.push(RuleInstance::new(Rule::Vertical, squares, 0, GroupSet::EMPTY));
+            }
+        }
+        output
+    }
+
+    //Every rule instance available to the controller of zugzwang
+    pub(crate) fn instances(&self) -> Vec<RuleInstance> {
+        let mut output = Vec::new();
+        claimeven(self, &mut output);
+        baseinverse(self, &mut output);
+        vertical(self, &mut output);
+        aftereven(self, &mut output);
+        lowinverse(self, &mut output);
+        highinverse(self, &mut output);
+        baseclaim(self, &mut output);
+        before(self, &mut output);
+        specialbefore(self, &mut output);
+        output
+    }
+
+    //Groups of the opponent containing all the given squares
+    fn refuted(&self, squares: u64) -> GroupSet {
+        GroupSet::containing(squares).intersection(self.threats)
+    }
+
+    //Upper squares of all the possible Claimevens
+    fn claimeven_uppers(&self) -> u64 {
+        self.even & self.empty & (self.empty << 1)
+    }
+
+    fn push(&self, output: &mut Vec<RuleInstance>, rule: Rule, squares: u64, claimevens: u64, solutions: GroupSet) {
+        //A rule which refutes nothing is of no use
+        if !solutions.is_empty() {
+            output.push(RuleInstance::new(rule, squares, claimevens, solutions));
+        }
+    }
+}
+
+//Iterates over the squares of a bitboard, as single-bit masks
+fn bits(mut mask: u64) -> impl Iterator<Item = u64> {
+    std::iter::from_fn(move || {
+        if mask == 0 {
+            None
+        } else {
+            let bit = mask & mask.wrapping_neg();
+            mask ^= bit;
+            Some(bit)
+        }
+    })
+}
+
+fn claimeven(context: &Context, output: &mut Vec<RuleInstance>) {
+    //Two empty squares directly above each other, the upper one being even:
+    //the controller answers a move in the lower square by taking the upper one
+    for upper in bits(context.claimeven_uppers()) {
+        let squares = upper | upper >> 1;
+        context.push(output, Rule::Claimeven, squares, squares, context.refuted(upper));
+    }
+}
+
+fn baseinverse(context: &Context, output: &mut Vec<RuleInstance>) {
+    //Two directly playable squares: the controller answers a move in one of them
+    //by taking the other one
+    let playable: Vec<u64> = bits(context.playable).collect();
+    for (i, &first) in playable.iter().enumerate() {
+        for &second in &playable[i + 1..] {
+            let squares = first | second;
+            context.push(output, Rule::Baseinverse, squares, 0, context.refuted(squares));
+        }
+    }
+}
+
+fn vertical(context: &Context, output: &mut Vec<RuleInstance>) {
+    //Two empty squares directly above each other, the upper one being odd:
+    //the controller gets at least one of them
+    let uppers = !context.even & context.empty & (context.empty << 1);
+    for upper in bits(uppers) {
+        let squares = upper | upper >> 1;
+        context.push(output, Rule::Vertical, squares, 0, context.refuted(squares));
+    }
+}
+
+fn aftereven(context: &Context, output: &mut Vec<RuleInstance>) {
+    //A group the controller can complete using only the upper squares of Claimevens.
+    //If the opponent never plays below those squares, the controller completes the
+    //group at the end of the game, so the opponent cannot complete any group needing
+    //squares above the Aftereven group in each of its columns.
+    let uppers = context.claimeven_uppers();
+    for group_mask in GROUP_MASKS {
+        let empty = group_mask & context.empty;
+        if group_mask & context.opponent != 0 || empty == 0 || empty & !uppers != 0 {
+            continue;
+        }
+
+        let claimevens = empty | empty >> 1;
+        let mut solutions = GroupSet::EMPTY;
+        for (group, &mask) in GROUP_MASKS.iter().enumerate() {
+            //Every column of the Aftereven group must contain a square of the group
+            //above the Aftereven square
+            if bits(empty).all(|square| mask & above(square) != 0) {
+                solutions = solutions.union(GroupSet::single(group));
+            }
+        }
+        solutions = solutions.intersection(context.threats);
+
+        //The Claimevens are played anyway
+        for upper in bits(empty) {
+            solutions = solutions.union(context.refuted(upper));
+        }
+
+        context.push(output, Rule::Aftereven, claimevens, claimevens, solutions);
+    }
+}
+
+fn lowinverse(context: &Context, output: &mut Vec<RuleInstance>) {
+    //Two Verticals in different columns: on top of the Verticals, the controller
+    //gets at least one of the two upper squares
+    let uppers: Vec<u64> = bits(!context.even & context.empty & (context.empty << 1)).collect();
+    for (i, &first) in uppers.iter().enumerate() {
+        for &second in &uppers[i + 1..] {
+            if column_of(first) == column_of(second) {
+                continue;
+            }
+            let solutions = context.refuted(first | second)
+                .union(context.refuted(first | first >> 1))
+                .union(context.refuted(second | second >> 1));
+            let squares = first | first >> 1 | second | second >> 1;
+            context.push(output, Rule::Lowinverse, squares, 0, solutions);
+        }
+    }
+}
+
+fn highinverse(context: &Context, output: &mut Vec<RuleInstance>) {
+    //Two columns with three empty squares directly above each other, the upper ones
+    //being even: the controller gets at least one of the two upper squares, one of the
+    //two middle squares, and one of the two highest squares in each column
+    let empty = context.empty;
+    let uppers: Vec<u64> = bits(context.even & empty & (empty << 1) & (empty << 2)).collect();
+    for (i, &first) in uppers.iter().enumerate() {
+        for &second in &uppers[i + 1..] {
+            if column_of(first) == column_of(second) {
+                continue;
+            }
+            let mut solutions = context.refuted(first | second)
+                .union(context.refuted(first >> 1 | second >> 1))
+                .union(context.refuted(first | first >> 1))
+                .union(context.refuted(second | second >> 1));
+            //If a lower square is directly playable, the controller also gets at least
+            //one of this square and the upper square of the other column
+            if first >> 2 & context.playable != 0 {
+                solutions = solutions.union(context.refuted(first >> 2 | second));
+            }
+            if second >> 2 & context.playable != 0 {
+                solutions = solutions.union(context.refuted(second >> 2 | first));
+            }
+            let squares = first | first >> 1 | first >> 2 | second | second >> 1 | second >> 2;
+            context.push(output, Rule::Highinverse, squares, 0, solutions);
+        }
+    }
+}
+
+fn baseclaim(context: &Context, output: &mut Vec<RuleInstance>) {
+    //Three directly playable squares and the even square above the second one: the
+    //controller gets one of the first square and the square above the second one, and
+    //one of the second and the third squares
+    let playable: Vec<u64> = bits(context.playable).collect();
+    for &second in &playable {
+        let above_second = second << 1;
+        if above_second & context.even & context.empty == 0 {
+            continue;
+        }
+        for &first in &playable {
+            for &third in &playable {
+                if first == second || third == second || first == third {
+                    continue;
+                }
+                let solutions = context.refuted(first | above_second)
+                    .union(context.refuted(second | third));
+                let squares = first | second | third | above_second;
+                context.push(output, Rule::Baseclaim, squares, 0, solutions);
+            }
+        }
+    }
+}
+
+//Empty squares of a potential Before group, if it can be used as one
+fn before_group_squares(context: &Context, group_mask: u64) -> Option<u64> {
+    let empty = group_mask & context.empty;
+    //No man of the opponent, at least one empty square, an empty successor for every
+    //empty square, and at most one empty square per column (which excludes vertical groups)
+    if group_mask & context.opponent != 0 || empty == 0 || (empty << 1) & !context.empty != 0
+        || empty & (empty << 1) != 0 {
+        None
+    } else {
+        Some(empty)
+    }
+}
+
+//Each empty square of the Before group is combined with its successor: into a Claimeven
+//if the successor is even, into a Vertical otherwise. Returns the squares used by the
+//pairs, the squares of the Claimevens, and the groups refuted by the pairs.
+fn before_pairs(context: &Context, empty: u64) -> (u64, u64, GroupSet) {
+    let mut squares = 0;
+    let mut claimevens = 0;
+    let mut solutions = GroupSet::EMPTY;
+    for square in bits(empty) {
+        let successor = square << 1;
+        let pair = square | successor;
+        squares |= pair;
+        if successor & context.even != 0 {
+            claimevens |= pair;
+            solutions = solutions.union(context.refuted(successor));
+        } else {
+            solutions = solutions.union(context.refuted(pair));
+        }
+    }
+    (squares, claimevens, solutions)
+}
+
+fn before(context: &Context, output: &mut Vec<RuleInstance>) {
+    //The opponent cannot get the successors of all the empty squares of the Before
+    //group without letting the controller complete the Before group first
+    for group_mask in GROUP_MASKS {
+        if let Some(empty) = before_group_squares(context, group_mask) {
+            let (squares, claimevens, pair_solutions) = before_pairs(context, empty);
+            let solutions = context.refuted(empty << 1).union(pair_solutions);
+            context.push(output, Rule::Before, squares, claimevens, solutions);
+        }
+    }
+}
+
+fn specialbefore(context: &Context, output: &mut Vec<RuleInstance>) {
+    //A Before where a directly playable empty square of the group is combined with
+    //another directly playable square into a Baseinverse, instead of being combined
+    //with its successor
+    for group_mask in GROUP_MASKS {
+        if let Some(empty) = before_group_squares(context, group_mask) {
+            for playable_square in bits(empty & context.playable) {
+                let (pair_squares, claimevens, pair_solutions) = before_pairs(context, empty ^ playable_square);
+                for extra in bits(context.playable & !pair_squares & !playable_square) {
+                    let solutions = context.refuted(empty << 1 | extra)
+                        .union(context.refuted(playable_square | extra))
+                        .union(pair_solutions);
+                    let squares = pair_squares | playable_square | extra;
+                    context.push(output, Rule::Specialbefore, squares, claimevens, solutions);
+                }
+            }
+        }
+    }
+}
+
+fn column_of(square: u64) -> usize {
+    square.trailing_zeros() as usize / (Board::HEIGHT + 1)
+}
+
+//Squares strictly above the given square, in the same column
+fn above(square: u64) -> u64 {
+    Board::column_mask(column_of(square)) & !(square | (square - 1))
+}
+
+pub fn claimevens(board: Board, controller: Player) -> Vec<RuleInstance> {
+    let mut output = Vec::new();
+    claimeven(&Context::new(board, controller), &mut output);
+    output
+}
+
+pub fn baseinverses(board: Board, controller: Player) -> Vec<RuleInstance> {
+    let mut output = Vec::new();
+    baseinverse(&Context::new(board, controller), &mut output);
+    output
+}
+
+pub fn verticals(board: Board, controller: Player) -> Vec<RuleInstance> {
+    let mut output = Vec::new();
+    vertical(&Context::new(board, controller), &mut output);
+    output
+}
+
+pub fn afterevens(board: Board, controller: Player) -> Vec<RuleInstance> {
+    let mut output = Vec::new();
+    aftereven(&Context::new(board, controller), &mut output);
+    output
+}
+
+pub fn lowinverses(board: Board, controller: Player) -> Vec<RuleInstance> {
+    let mut output = Vec::new();
+    lowinverse(&Context::new(board, controller), &mut output);
+    output
+}
+
+pub fn highinverses(board: Board, controller: Player) -> Vec<RuleInstance> {
+    let mut output = Vec::new();
+    highinverse(&Context::new(board, controller), &mut output);
+    output
+}
+
+pub fn baseclaims(board: Board, controller: Player) -> Vec<RuleInstance> {
+    let mut output = Vec::new();
+    baseclaim(&Context::new(board, controller), &mut output);
+    output
+}
+
+pub fn befores(board: Board, controller: Player) -> Vec<RuleInstance> {
+    let mut output = Vec::new();
+    before(&Context::new(board, controller), &mut output);
+    output
+}
+
+pub fn specialbefores(board: Board, controller: Player) -> Vec<RuleInstance> {
+    let mut output = Vec::new();
+    specialbefore(&Context::new(board, controller), &mut output);
+    output
+}
+
+//Every rule instance available to the controller of zugzwang
+pub fn rule_instances(board: Board, controller: Player) -> Vec<RuleInstance> {
+    Context::new(board, controller).instances()
+}
+
+//Groups of the opponent of the controller which still have to be refuted
+pub fn threatening_groups(board: Board, controller: Player) -> GroupSet {
+    Context::new(board, controller).threats()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::testing::play;
+    use crate::groups::group_name;
+    use crate::strategy;
+
+    //The only instance of the rule on these squares
+    fn instance(instances: &[RuleInstance], rule: Rule, squares: &str) -> RuleInstance {
+        let found: Vec<&RuleInstance> = instances.iter()
+            .filter(|instance| instance.rule() == rule && instance.squares().to_string() == squares)
+            .collect();
+        assert_eq!(found.len(), 1, "{rule} on {squares}: {found:?}");
+        *found[0]
+    }
+
+    fn names(groups: GroupSet) -> Vec<String> {
+        let mut names: Vec<String> = groups.iter().map(group_name).collect();
+        names.sort();
+        names
+    }
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn lowinverse() {
+        //Black gets one of a3 and b3, and one square of each Vertical
+        let found = instance(&lowinverses(Board::new(), Player::Black), Rule::Lowinverse, "a2/a3, b2/b3");
+        assert!(found.claimevens().is_empty());
+        assert_eq!(names(found.solutions()), sorted(&["a3-d3", "a1-a4", "a2-a5", "b1-b4", "b2-b5"]));
+    }
+
+    #[test]
+    fn highinverse() {
+        //a2 and b2 are directly playable, but no group holds a2 and b4, or b2 and a4
+        let board = play(&[0, 1]);
+        let found = instance(&highinverses(board, Player::White), Rule::Highinverse, "a2/a3/a4, b2/b3/b4");
+        assert!(found.claimevens().is_empty());
+        assert_eq!(names(found.solutions()), sorted(&["a4-d4", "a3-d3", "a2-a5", "a3-a6", "b1-b4", "b2-b5", "b3-b6"]));
+    }
+
+    //Both ends of the three playable squares can be paired with the square above the middle
+    #[test]
+    fn baseclaim() {
+        let board = play(&[0, 1]);
+        let mut solutions: Vec<Vec<String>> = baseclaims(board, Player::White).into_iter()
+            .filter(|found| found.squares().to_string() == "b2, c1/c2, d1")
+            .inspect(|found| assert!(found.claimevens().is_empty()))
+            .map(|found| names(found.solutions()))
+            .collect();
+        solutions.sort();
+        assert_eq!(solutions, [
+            //White gets one of b2 and c2, and one of c1 and d1
+            sorted(&["a2-d2", "b2-e2", "b1-e1", "c1-f1"]),
+            //White gets one of d1 and c2, and one of c1 and b2
+            sorted(&["a4-d1"])
+        ]);
+    }
+
+    //White completes a1-d1 unless Black takes d2, which the Claimeven on d1/d2 prevents
+    #[test]
+    fn before() {
+        let board = play(&[0, 6, 1, 6, 2]);
+        let found = instance(&befores(board, Player::White), Rule::Before, "d1/d2");
+        assert_eq!(found.claimevens().to_string(), "d1/d2");
+        assert_eq!(names(found.solutions()),
+                   sorted(&["a2-d2", "b2-e2", "c2-f2", "d2-g2", "d1-d4", "d2-d5", "d2-g5", "a5-d2", "b4-e1"]));
+    }
+
+    //The same Before group, with d1 paired with e1 in a Baseinverse instead of its successor
+    #[test]
+    fn specialbefore() {
+        let board = play(&[0, 6, 1, 6, 2]);
+        let found = instance(&specialbefores(board, Player::White), Rule::Specialbefore, "d1, e1");
+        assert!(found.claimevens().is_empty());
+        assert_eq!(names(found.solutions()), sorted(&["b4-e1", "d1-g1"]));
+    }
+
+    #[test]
+    fn display() {
+        let board = play(&[0, 6, 1, 6, 2]);
+        let found = instance(&befores(board, Player::White), Rule::Before, "d1/d2");
+        assert_eq!(found.to_string(), "Before on d1/d2 (using the Claimevens on d1/d2) refutes a2-d2, b2-e2, c2-f2, d2-g2, d1-d4, d2-d5, d2-g5, b4-e1, a5-d2");
+        let found = instance(&specialbefores(board, Player::White), Rule::Specialbefore, "d1, e1");
+        assert_eq!(found.to_string(), "Specialbefore on d1, e1 refutes d1-g1, b4-e1");
+        let found = instance(&claimevens(Board::new(), Player::Black), Rule::Claimeven, "a1/a2");
+        assert_eq!(found.to_string(), "Claimeven on a1/a2 refutes a2-d2, a1-a4, a2-a5, a2-d5");
+        let pairing = Context::new(board, Player::White).pairings().into_iter()
+            .find(|pairing| pairing.rule() == Rule::Baseinverse)
+            .unwrap();
+        assert_eq!(pairing.to_string(), "Baseinverse on a2, d1 refutes nothing but pairs squares");
+
+        //The explanation of a whole strategy, one instance per line
+        let board = play(&[5, 4, 1, 1, 5, 0, 6, 0, 0, 3, 5, 5, 3, 0, 5, 6, 5, 2, 0, 0, 1, 4, 1]);
+        assert_eq!(strategy::find_strategy(board).unwrap().to_string(), concat!(
+            "White controls zugzwang thanks to the odd threat on c3 and wins:\n",
+            "Claimeven on c2/c3 refutes a1-d4, b2-e5\n",
+            "Claimeven on d5/d6 refutes d3-d6, d6-g3\n",
+            "Claimeven on e3/e4 refutes d4-g4, e1-e4, e2-e5, e3-e6\n",
+            "Claimeven on g3/g4 refutes d4-g4, g2-g5, g3-g6"
+        ));
+    }
+}
@@ -0,0 +1,471 @@
+/*
+ Not in the original program: a perfect solver based on Pascal Pons' one
+*/
+
+//Negamax with alpha-beta pruning on the bitboards. Internally, a score is positive when the
+//player to move wins, and the sooner he wins the bigger it is: winning with the last move
+//of the board is worth 1, and a loss is the opposite of the opponent's win.
+
+use std::cmp::Ordering;
+use std::mem;
+use crate::board::Board;
+use crate::control::{Monitor, SearchControl};
+
+//Exact value of a position, for the player to move. The distances count the moves of both
+//players until the end of the game, the last one included.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Score {
+    Win(usize),
+    Draw,
+    Loss(usize)
+}
+
+impl Score {
+    fn from_value(value: i32, filled: usize) -> Self {
+        //The winner plays his last move when the board holds this many men
+        let winning_position = |value: i32, winner_parity: usize| {
+            let position = (Board::SQUARES as i32 + 1 - 2 * value) as usize;
+            if position & 1 == winner_parity {
+                position
+            } else {
+                position - 1
+            }
+        };
+        match value.cmp(&0) {
+            Ordering::Greater => Score::Win(winning_position(value, filled & 1) + 1 - filled),
+            Ordering::Equal => Score::Draw,
+            Ordering::Less => Score::Loss(winning_position(-value, !filled & 1) + 1 - filled)
+        }
+    }
+
+    //The score of the position before the move leading to this one
+    pub fn parent(self) -> Self {
+        match self {
+            Score::Win(distance) => Score::Loss(distance + 1),
+            Score::Draw => Score::Draw,
+            Score::Loss(distance) => Score::Win(distance + 1)
+        }
+    }
+
+    fn rank(self) -> i32 {
+        match self {
+            Score::Win(distance) => (Board::SQUARES + 1 - distance) as i32,
+            Score::Draw => 0,
+            Score::Loss(distance) => distance as i32 - (Board::SQUARES + 1) as i32
+        }
+    }
+}
+
+//What the solver knows about a position: the exact score once the search is over, or the
+//bounds it already proved when it was cancelled
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct ScoreBounds {
+    lower: Score,
+    upper: Score
+}
+
+impl ScoreBounds {
+    fn exactly(score: Score) -> Self {
+        Self {
+            lower: score,
+            upper: score
+        }
+    }
+
+    pub fn lower(self) -> Score {
+        self.lower
+    }
+
+    pub fn upper(self) -> Score {
+        self.upper
+    }
+
+    pub fn exact(self) -> Option<Score> {
+        if self.lower == self.upper {
+            Some(self.lower)
+        } else {
+            None
+        }
+    }
+
+    //The bounds of the position before the move leading to this one
+    pub fn parent(self) -> Self {
+        Self {
+            lower: self.upper.parent(),
+            upper: self.lower.parent()
+        }
+    }
+}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    //Quicker wins and slower losses are better
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+const MIN_VALUE: i32 = -(Board::SQUARES as i32) / 2 + 3;
+pub(crate) const COLUMN_ORDER: [usize; Board::WIDTH] = [3, 2, 4, 1, 5, 0, 6];
+const CHECK_INTERVAL: u64 = 4096; //Nodes between two checks of the cancellation token
+
+//Stores upper bounds of the values. Only a part of the key is kept: since the keys use 49
+//bits and the size is a prime bigger than 2^17, the rest of the key is given by the index.
+struct TranspositionTable {
+    keys: Vec<u32>,
+    values: Vec<i8>
+}
+
+impl TranspositionTable {
+    const DEFAULT_SIZE: usize = 8388617; //First prime after 2^23
+    const MIN_SIZE: usize = 131101; //First prime after 2^17
+    const ENTRY_SIZE: usize = mem::size_of::<u32>() + mem::size_of::<i8>();
+
+    fn new(size: usize) -> Self {
+        Self {
+            keys: vec![0; size],
+            values: vec![0; size]
+        }
+    }
+
+    //The largest prime size fitting in the memory, but never less than MIN_SIZE
+    fn with_memory(memory: usize) -> Self {
+        let is_prime = |n: usize| (2..).take_while(|d| d * d <= n).all(|d| !n.is_multiple_of(d));
+        let largest = (memory / Self::ENTRY_SIZE).max(Self::MIN_SIZE);
+        Self::new((Self::MIN_SIZE..=largest).rev().find(|&size| is_prime(size)).unwrap())
+    }
+
+    fn size(&self) -> u64 {
+        self.keys.len() as u64
+    }
+
+    fn put(&mut self, key: u64, value: i8) {
+        let index = (key % self.size()) as usize;
+        self.keys[index] = key as u32;
+        self.values[index] = value;
+    }
+
+    fn get(&self, key: u64) -> Option<i8> {
+        let index = (key % self.size()) as usize;
+        if self.keys[index] == key as u32 && self.values[index] != 0 {
+            Some(self.values[index])
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Solver {
+    table: TranspositionTable,
+    node_count: u64,
+    cancelled: bool
+}
+
+impl Solver {
+    //With a table of about 40 MB
+    pub fn new() -> Self {
+        Self::with_table(TranspositionTable::new(TranspositionTable::DEFAULT_SIZE))
+    }
+
+    //The memory of the table is in bytes, it never goes below about 640 kB
+    pub fn with_memory(memory: usize) -> Self {
+        Self::with_table(TranspositionTable::with_memory(memory))
+    }
+
+    fn with_table(table: TranspositionTable) -> Self {
+        Self {
+            table,
+            node_count: 0,
+            cancelled: false
+        }
+    }
+
+    pub fn memory(&self) -> usize {
+        self.table.keys.len() * TranspositionTable::ENTRY_SIZE
+    }
+
+    //Number of positions explored since the creation of the solver
+    pub fn node_count(&self) -> u64 {
+        self.node_count
+    }
+
+    //Playable squares which do not lose immediately, or 0 if every move loses
+    fn non_losing_moves(board: Board) -> u64 {
+        let opponent = board.player_to_play().opponent();
+        let opponent_threats = board.winning_positions(opponent);
+        let mut playable = board.playable_positions();
+        let forced = playable & opponent_threats;
+        if forced != 0 {
+            if forced & (forced - 1) != 0 {
+                return 0; //Two immediate threats, we cannot stop both
+            }
+            playable = forced;
+        }
+        playable & !(opponent_threats >> 1) //Never play below a threat of the opponent
+    }
+
+    //Moves creating more threats are tried first, then the ones closer to the center
+    fn ordered_moves(board: Board, moves: u64) -> Vec<u64> {
+        let player = board.player_to_play();
+        let mut ordered: Vec<(u32, u64)> = Vec::with_capacity(Board::WIDTH);
+        for column in COLUMN_ORDER {
+            let square = moves & Board::column_mask(column);
+            if square != 0 {
+                let threats = board.make_move_on(square).winning_positions(player).count_ones();
+                let position = ordered.iter().position(|&(other, _)| other < threats).unwrap_or(ordered.len());
+                ordered.insert(position, (threats, square));
+            }
+        }
+        ordered.into_iter().map(|(_, square)| square).collect()
+    }
+
+    //The current player cannot win immediately. Once cancelled, the values are meaningless
+    //and nothing is stored anymore.
+    fn negamax(&mut self, board: Board, mut alpha: i32, mut beta: i32, monitor: &mut Monitor) -> i32 {
+        self.node_count += 1;
+        if self.node_count.is_multiple_of(CHECK_INTERVAL) {
+            self.cancelled |= monitor.is_cancelled();
+            monitor.report(self.node_count as usize, || (None, None));
+        }
+        if self.cancelled {
+            return alpha;
+        }
+        let filled = board.filled_squares() as i32;
+
+        let moves = Self::non_losing_moves(board);
+        if moves == 0 {
+            return -(Board::SQUARES as i32 - filled) / 2; //The opponent wins with his next move
+        }
+        if filled >= Board::SQUARES as i32 - 2 {
+            return 0; //Nobody can win anymore
+        }
+
+        //The opponent cannot win with his next move
+        let min = -(Board::SQUARES as i32 - 2 - filled) / 2;
+        if alpha < min {
+            alpha = min;
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+
+        //We cannot win with our next move
+        let mut max = (Board::SQUARES as i32 - 1 - filled) / 2;
+        if let Some(value) = self.table.get(board.key()) {
+            max = value as i32 + MIN_VALUE - 1;
+        }
+        if beta > max {
+            beta = max;
+            if alpha >= beta {
+                return beta;
+            }
+        }
+
+        for square in Self::ordered_moves(board, moves) {
+            let score = -self.negamax(board.make_move_on(square), -beta, -alpha, monitor);
+            if self.cancelled {
+                return alpha;
+            }
+            if score >= beta {
+                return score;
+            }
+            if score > alpha {
+                alpha = score;
+            }
+        }
+
+        self.table.put(board.key(), (alpha - MIN_VALUE + 1) as i8);
+        alpha
+    }
+
+    pub fn solve(&mut self, board: Board) -> Score {
+        self.solve_with_control(board, &mut SearchControl::default()).exact().unwrap()
+    }
+
+    //A cancelled search returns the bounds proved so far, the other ones the exact score.
+    //The progress reports only give the nodes, counted since the creation of the solver, and
+    //the elapsed time.
+    pub fn solve_with_control(&mut self, board: Board, control: &mut SearchControl) -> ScoreBounds {
+        let filled = board.filled_squares();
+        if board.is_endgame() {
+            //Either the previous move won the game, or the board is full
+            return ScoreBounds::exactly(if board.is_full() && !board.is_winning() {
+                Score::Draw
+            } else {
+                Score::Loss(0)
+            });
+        }
+        if board.get_winning_move().is_some() {
+            return ScoreBounds::exactly(Score::Win(1));
+        }
+
+        let mut monitor = Monitor::new(control);
+        self.cancelled = false;
+
+        //Null window searches, narrowing the possible values until they meet
+        let mut min = -(Board::SQUARES as i32 - filled as i32) / 2;
+        let mut max = (Board::SQUARES as i32 + 1 - filled as i32) / 2;
+        while min < max {
+            let mut middle = min + (max - min) / 2;
+            //Look for wins and losses before the exact distance
+            if middle <= 0 && min / 2 < middle {
+                middle = min / 2;
+            } else if middle >= 0 && max / 2 > middle {
+                middle = max / 2;
+            }
+            let value = self.negamax(board, middle, middle + 1, &mut monitor);
+            if self.cancelled {
+                break;
+            }
+            if value <= middle {
+                max = value;
+            } else {
+                min = value;
+            }
+        }
+        ScoreBounds {
+            lower: Score::from_value(min, filled),
+            upper: Score::from_value(max, filled)
+        }
+    }
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn solve(board: Board) -> Score {
+    Solver::new().solve(board)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::testing::{play, RandomGames};
+    use crate::control::CancellationToken;
+
+    //Plain alpha-beta on the number of men left when the game ends, without any of the
+    //tricks of the solver: a win with the n-th man of the board is worth 43 - n
+    fn reference(board: Board, mut alpha: i32, beta: i32) -> i32 {
+        let filled = board.filled_squares() as i32;
+        if board.get_winning_move().is_some() {
+            return Board::SQUARES as i32 - filled;
+        }
+        let mut best = -(Board::SQUARES as i32 + 1);
+        for column in COLUMN_ORDER {
+            let Ok(new_board) = board.make_move(column) else {
+                continue;
+            };
+            let value = if new_board.is_full() {
+                0
+            } else {
+                -reference(new_board, -beta, -alpha)
+            };
+            best = best.max(value);
+            alpha = alpha.max(value);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+
+    fn reference_score(board: Board) -> Score {
+        let value = reference(board, -(Board::SQUARES as i32 + 1), Board::SQUARES as i32 + 1);
+        let end = |value: i32| Board::SQUARES + 1 - value.unsigned_abs() as usize - board.filled_squares();
+        match value.cmp(&0) {
+            Ordering::Greater => Score::Win(end(value)),
+            Ordering::Equal => Score::Draw,
+            Ordering::Less => Score::Loss(end(value))
+        }
+    }
+
+    //Games played at random until the board holds this many men, skipping the ones decided
+    //within two moves
+    fn random_positions(filled: usize, count: usize) -> Vec<Board> {
+        let mut games = RandomGames::new(12345);
+        let mut positions = Vec::new();
+        while positions.len() < count {
+            if let Some(board) = games.position(filled) {
+                if board.get_winning_move().is_none() && Solver::non_losing_moves(board) != 0 {
+                    positions.push(board);
+                }
+            }
+        }
+        positions
+    }
+
+    #[test]
+    fn immediate_results() {
+        assert_eq!(solve(play(&[0, 1, 0, 1, 0, 1])), Score::Win(1));
+        //White threatens a1 and e1
+        assert_eq!(solve(play(&[1, 1, 2, 2, 3])), Score::Loss(2));
+        //The previous move won
+        assert_eq!(solve(play(&[0, 1, 0, 1, 0, 1, 0])), Score::Loss(0));
+    }
+
+    #[test]
+    fn matches_a_reference_search() {
+        let mut solver = Solver::new();
+        for board in random_positions(28, 30) {
+            assert_eq!(solver.solve(board), reference_score(board), "{board:?}");
+        }
+    }
+
+    //Collisions are more frequent in a small table, but the scores stay the same
+    #[test]
+    fn small_table() {
+        let mut solver = Solver::with_memory(0);
+        assert!(solver.memory() < 1 << 20);
+        assert!(Solver::with_memory(100 << 20).memory() <= 100 << 20);
+        for board in random_positions(28, 10) {
+            assert_eq!(solver.solve(board), reference_score(board), "{board:?}");
+        }
+    }
+
+    //The score of a position is the best score of its children, so the bounds of the null
+    //window searches and the table agree from one position to the next
+    #[test]
+    fn consistent_with_the_children() {
+        let mut solver = Solver::new();
+        for board in random_positions(24, 10) {
+            let best = (0..Board::WIDTH)
+                .filter_map(|column| board.make_move(column).ok())
+                .map(|new_board| solver.solve(new_board).parent())
+                .max();
+            assert_eq!(Some(solver.solve(board)), best, "{board:?}");
+            assert_eq!(solver.solve(board.symmetric_board()), solver.solve(board));
+        }
+    }
+
+    #[test]
+    fn cancelled_search_keeps_its_bounds() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let board = play(&[3, 3, 3, 3]);
+        let bounds = Solver::new().solve_with_control(board, &mut SearchControl::new(token));
+        assert_eq!(bounds.exact(), None);
+        assert!(bounds.lower() < bounds.upper());
+        assert_eq!(bounds.parent().upper(), bounds.lower().parent());
+
+        let board = play(&[0, 1, 0, 1, 0, 1]);
+        let token = CancellationToken::new();
+        token.cancel();
+        assert_eq!(Solver::new().solve_with_control(board, &mut SearchControl::new(token)).exact(), Some(Score::Win(1)));
+    }
+
+    //Allis and Allen: the first player wins with the last man of the board. This takes
+    //minutes, run it with --ignored.
+    #[test]
+    #[ignore]
+    fn empty_board_is_a_first_player_win() {
+        assert_eq!(solve(Board::new()), Score::Win(41));
+    }
+}
@@ -0,0 +1,340 @@
+/*
+ evaluate.c
+*/
+
+//Allis' problem solving: every group of the opponent of the controller of zugzwang is a
+//problem, and every rule instance is a solution to some of them. We look for a set of
+//pairwise compatible rule instances solving every problem.
+
+use std::fmt;
+use crate::board::{self, Board, Player};
+use crate::groups::GroupSet;
+use crate::rules::{Context, Rule, RuleInstance, EVEN_ROWS};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Outcome {
+    AtLeastDraw, //The controller of zugzwang cannot lose
+    Win //The controller of zugzwang wins
+}
+
+#[derive(Clone, Debug)]
+pub struct Strategy {
+    controller: Player,
+    outcome: Outcome,
+    odd_threat: Option<(usize, usize)>,
+    instances: Vec<RuleInstance>
+}
+
+impl Strategy {
+    pub fn controller(&self) -> Player {
+        self.controller
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        self.outcome
+    }
+
+    //The odd threat which gives the control of zugzwang to White, if any
+    pub fn odd_threat(&self) -> Option<(usize, usize)> {
+        self.odd_threat
+    }
+
+    //The rule instances the controller of zugzwang follows
+    pub fn instances(&self) -> &[RuleInstance] {
+        &self.instances
+    }
+}
+
+impl fmt::Display for Strategy {
+    //The controller of zugzwang, then one rule instance per line
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} controls zugzwang", self.controller)?;
+        if let Some((column, row)) = self.odd_threat {
+            write!(f, " thanks to the odd threat on {}", board::square_name(column, row))?;
+        }
+        match self.outcome {
+            Outcome::AtLeastDraw => write!(f, " and cannot lose:")?,
+            Outcome::Win => write!(f, " and wins:")?
+        }
+        for instance in &self.instances {
+            write!(f, "\n{}", instance)?;
+        }
+        Ok(())
+    }
+}
+
+fn columns(squares: u64) -> u8 {
+    let mut result = 0;
+    for column in 0..Board::WIDTH {
+        if squares & Board::column_mask(column) != 0 {
+            result |= 1 << column;
+        }
+    }
+    result
+}
+
+fn compatible(first: &RuleInstance, second: &RuleInstance) -> bool {
+    let first_squares = first.squares().mask();
+    let second_squares = second.squares().mask();
+
+    //Squares can only be shared between rules relying on the same Claimevens. Claimevens
+    //always start on an odd row, so overlapping Claimevens are always equal.
+    let first_exclusive = first_squares & !first.claimevens().mask();
+    let second_exclusive = second_squares & !second.claimevens().mask();
+    if first_exclusive & second_squares != 0 || second_exclusive & first_squares != 0 {
+        return false;
+    }
+
+    //The inverses change the parity of their columns when they are played, which could
+    //break the Claimevens an Aftereven or a Before rely on in those columns
+    let is_inverse = |rule| matches!(rule, Rule::Lowinverse | Rule::Highinverse);
+    let is_before = |rule| matches!(rule, Rule::Aftereven | Rule::Before | Rule::Specialbefore);
+    if (is_inverse(first.rule()) && is_before(second.rule())) || (is_before(first.rule()) && is_inverse(second.rule())) {
+        return columns(first_squares) & columns(second_squares) == 0;
+    }
+
+    true
+}
+
+struct ProblemSolver<'a> {
+    context: &'a Context,
+    instances: &'a [RuleInstance],
+    solvers: Vec<Vec<usize>>, //Instances solving each group
+    users: Vec<Vec<usize>>, //Instances using each square, indexed like the Board bits
+    budget: usize
+}
+
+impl<'a> ProblemSolver<'a> {
+    fn new(context: &'a Context, instances: &'a [RuleInstance], problems: GroupSet) -> Self {
+        let mut solvers = vec![Vec::new(); crate::groups::GROUP_COUNT];
+        let mut users = vec![Vec::new(); u64::BITS as usize];
+        for (index, instance) in instances.iter().enumerate() {
+            for group in instance.solutions().intersection(problems).iter() {
+                solvers[group].push(index);
+            }
+            let mut squares = instance.squares().mask();
+            while squares != 0 {
+                users[squares.trailing_zeros() as usize].push(index);
+                squares &= squares - 1;
+            }
+        }
+
+        Self {
+            context,
+            instances,
+            solvers,
+            users,
+            budget: 5000
+        }
+    }
+
+    //The next problem to work on: the group with the fewest possible solutions first, then
+    //the squares the controller cannot follow up on
+    fn candidates(&self, problems: GroupSet, used: u64, allowed: &[bool]) -> Option<Vec<usize>> {
+        if problems.is_empty() {
+            let unpaired = self.context.unpaired(self.context.empty() & !used);
+            if unpaired == 0 {
+                return None;
+            }
+            return Some(self.users[unpaired.trailing_zeros() as usize].clone());
+        }
+
+        let mut hardest = None;
+        let mut hardest_count = usize::MAX;
+        for group in problems.iter() {
+            let count = self.solvers[group].iter().filter(|&&index| allowed[index]).count();
+            if count < hardest_count {
+                hardest = Some(group);
+                hardest_count = count;
+            }
+        }
+        Some(self.solvers[hardest.unwrap()].clone())
+    }
+
+    fn solve(&mut self, problems: GroupSet, used: u64, allowed: &mut [bool], chosen: &mut Vec<usize>) -> bool {
+        let candidates = match self.candidates(problems, used, allowed) {
+            Some(candidates) => candidates,
+            None => return true //Every problem is solved
+        };
+        if self.budget == 0 {
+            return false; //We give up, the position remains unproven
+        }
+        self.budget -= 1;
+
+        for index in candidates {
+            if !allowed[index] {
+                continue;
+            }
+
+            let instance = &self.instances[index];
+            let mut next_allowed = allowed.to_vec();
+            for (other, other_allowed) in next_allowed.iter_mut().enumerate() {
+                if *other_allowed && !compatible(instance, &self.instances[other]) {
+                    *other_allowed = false;
+                }
+            }
+
+            chosen.push(index);
+            let next_problems = problems.difference(instance.solutions());
+            let next_used = used | instance.squares().mask();
+            if self.solve(next_problems, next_used, &mut next_allowed, chosen) {
+                return true;
+            }
+            chosen.pop();
+
+            //Every solution using this instance has been tried already
+            allowed[index] = false;
+        }
+
+        false
+    }
+}
+
+//Looks for a set of compatible instances solving every problem, starting from the
+//instances which have to be used anyway
+fn solve_problems(context: &Context, mut chosen: Vec<RuleInstance>) -> Option<Vec<RuleInstance>> {
+    let mut instances = context.instances();
+    instances.extend(context.pairings());
+    let mut problems = context.threats();
+    let mut used = 0;
+    for instance in &chosen {
+        problems = problems.difference(instance.solutions());
+        used |= instance.squares().mask();
+    }
+
+    let mut allowed: Vec<bool> = instances.iter()
+        .map(|instance| chosen.iter().all(|other| compatible(instance, other)))
+        .collect();
+    let mut indices = Vec::new();
+    if ProblemSolver::new(context, &instances, problems).solve(problems, used, &mut allowed, &mut indices) {
+        chosen.extend(indices.into_iter().map(|index| instances[index]));
+        Some(chosen)
+    } else {
+        None
+    }
+}
+
+//Tries to prove the position with Allis' rules. With White to move, Black controls zugzwang
+//and may prove that he cannot lose. With Black to move, White can only control zugzwang
+//with an odd threat, and may prove that he wins.
+pub fn find_strategy(board: Board) -> Option<Strategy> {
+    if board.is_endgame() {
+        return None;
+    }
+
+    match board.player_to_play() {
+        Player::White => {
+            let instances = solve_problems(&Context::new(board, Player::Black), Vec::new())?;
+            Some(Strategy {
+                controller: Player::Black,
+                outcome: Outcome::AtLeastDraw,
+                odd_threat: None,
+                instances
+            })
+        }
+        Player::Black => {
+            //The threat must be odd, and the lowest empty square of its column must be
+            //even so that White can follow up up to the threat
+            let playable = board.playable_positions();
+            let threats = board.winning_positions(Player::White) & !EVEN_ROWS & !playable;
+            for column in 0..Board::WIDTH {
+                let column_threats = threats & Board::column_mask(column);
+                if column_threats == 0 || playable & Board::column_mask(column) & EVEN_ROWS == 0 {
+                    continue;
+                }
+                let threat = column_threats & column_threats.wrapping_neg(); //Lowest threat
+
+                let context = Context::with_odd_threat(board, threat);
+                if let Some(instances) = solve_problems(&context, vec![context.threat_claimeven(threat)]) {
+                    let row = threat.trailing_zeros() as usize % (Board::HEIGHT + 1);
+                    return Some(Strategy {
+                        controller: Player::White,
+                        outcome: Outcome::Win,
+                        odd_threat: Some((column, row)),
+                        instances
+                    });
+                }
+            }
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::testing::{play, RandomGames};
+    use crate::solver::{Score, Solver};
+
+    fn rules(strategy: &Strategy) -> Vec<Rule> {
+        let mut rules: Vec<Rule> = Vec::new();
+        for instance in strategy.instances() {
+            if !rules.contains(&instance.rule()) {
+                rules.push(instance.rule());
+            }
+        }
+        rules
+    }
+
+    //What the solver says about a strategy, from the player to move
+    fn is_sound(board: Board, strategy: &Strategy, solver: &mut Solver) -> bool {
+        let score = solver.solve(board);
+        match (strategy.controller(), strategy.outcome()) {
+            (Player::Black, Outcome::AtLeastDraw) => board.player_to_play() == Player::White && score <= Score::Draw,
+            (Player::White, Outcome::Win) => board.player_to_play() == Player::Black && matches!(score, Score::Loss(_)),
+            _ => false
+        }
+    }
+
+    //White controls zugzwang with an odd threat, and claims the even squares everywhere else
+    #[test]
+    fn odd_threat_and_claimeven() {
+        let board = play(&[5, 4, 1, 1, 5, 0, 6, 0, 0, 3, 5, 5, 3, 0, 5, 6, 5, 2, 0, 0, 1, 4, 1]);
+        let strategy = find_strategy(board).unwrap();
+        assert_eq!(strategy.controller(), Player::White);
+        assert_eq!(strategy.outcome(), Outcome::Win);
+        assert!(strategy.odd_threat().is_some());
+        assert_eq!(rules(&strategy), [Rule::Claimeven]);
+        assert!(is_sound(board, &strategy, &mut Solver::new()));
+    }
+
+    #[test]
+    fn aftereven() {
+        let board = play(&[6, 3, 0, 4, 3, 1, 2, 3, 1, 3, 1, 4, 6, 0, 5, 1, 2, 5]);
+        let strategy = find_strategy(board).unwrap();
+        assert_eq!(strategy.controller(), Player::Black);
+        assert_eq!(rules(&strategy), [Rule::Aftereven]);
+        assert!(is_sound(board, &strategy, &mut Solver::new()));
+    }
+
+    #[test]
+    fn aftereven_and_claimeven() {
+        let board = play(&[5, 6, 3, 6, 0, 2, 5, 0, 3, 3, 0, 6, 6, 5, 5, 5, 2, 6, 2, 0, 6, 5, 0, 2, 0, 3, 3, 3, 1, 1]);
+        let strategy = find_strategy(board).unwrap();
+        assert_eq!(strategy.controller(), Player::Black);
+        let rules = rules(&strategy);
+        assert!(rules.contains(&Rule::Aftereven) && rules.contains(&Rule::Claimeven) && rules.len() == 2);
+        assert!(is_sound(board, &strategy, &mut Solver::new()));
+    }
+
+    //Every strategy found on random positions agrees with the solver
+    #[test]
+    fn strategies_are_sound() {
+        let mut solver = Solver::new();
+        let mut games = RandomGames::new(777);
+        let mut found = 0;
+        for game in 0..3000 {
+            let Some(board) = games.position(20 + game % 14) else {
+                continue;
+            };
+            if board.get_winning_move().is_some() {
+                continue;
+            }
+            if let Some(strategy) = find_strategy(board) {
+                found += 1;
+                assert!(is_sound(board, &strategy, &mut solver), "{board:?}\n{strategy}");
+            }
+        }
+        assert!(found >= 20, "only {found} strategies found");
+    }
+}
@@ -0,0 +1,279 @@
+/*
+ Not in the original program: a transposition table kept between the searches
+*/
+
+//Proof and disproof numbers of positions, in a table of fixed size. The numbers depend on
+//the player at the root of the search and on what it tries to prove, so each entry also
+//keeps this context. The table is allocated when the first entry is stored.
+
+use std::mem;
+
+//What happens when a position falls on a pair of entries holding two other ones
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub enum Replacement {
+    #[default]
+    Always, //The oldest position is replaced
+    //An unsolved position is replaced before a proved or disproved one, so the results of the
+    //previous searches survive
+    KeepSolved
+}
+
+#[derive(Copy, Clone, Default, Debug)]
+struct Entry {
+    key: u64, //Keys are stored plus one, so that 0 marks an empty entry
+    proof: u32,
+    disproof: u32,
+    context: u8
+}
+
+impl Entry {
+    fn is_solved(&self) -> bool {
+        self.key != 0 && (self.proof == 0 || self.disproof == 0)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct TableStats {
+    capacity: usize,
+    used: usize,
+    lookups: u64,
+    hits: u64
+}
+
+impl TableStats {
+    //Number of entries
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn lookups(&self) -> u64 {
+        self.lookups
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn hit_rate(&self) -> f64 {
+        if self.lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.lookups as f64
+        }
+    }
+
+    //Fraction of the entries in use
+    pub fn fill(&self) -> f64 {
+        self.used as f64 / self.capacity as f64
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TranspositionTable {
+    entries: Vec<Entry>,
+    capacity: usize,
+    replacement: Replacement,
+    used: usize,
+    lookups: u64,
+    hits: u64
+}
+
+impl TranspositionTable {
+    pub const ENTRY_SIZE: usize = mem::size_of::<Entry>();
+    pub const DEFAULT_MEMORY: usize = (1 << 20) * Self::ENTRY_SIZE;
+
+    //The memory is in bytes, there is always at least one pair of entries
+    pub fn new(memory: usize, replacement: Replacement) -> Self {
+        Self {
+            entries: Vec::new(),
+            capacity: (memory / Self::ENTRY_SIZE / 2).max(1) * 2,
+            replacement,
+            used: 0,
+            lookups: 0,
+            hits: 0
+        }
+    }
+
+    pub fn memory(&self) -> usize {
+        self.capacity * Self::ENTRY_SIZE
+    }
+
+    pub fn replacement(&self) -> Replacement {
+        self.replacement
+    }
+
+    pub fn set_replacement(&mut self, replacement: Replacement) {
+        self.replacement = replacement;
+    }
+
+    pub fn stats(&self) -> TableStats {
+        TableStats {
+            capacity: self.capacity,
+            used: self.used,
+            lookups: self.lookups,
+            hits: self.hits
+        }
+    }
+
+    //Forgets every position and resets the statistics
+    pub fn clear(&mut self) {
+        *self = Self::new(self.memory(), self.replacement);
+    }
+
+    //The positions are moved to the new entries, some of them are lost when shrinking
+    pub fn resize(&mut self, memory: usize) {
+        let mut table = Self::new(memory, self.replacement);
+        table.lookups = self.lookups;
+        table.hits = self.hits;
+        for entry in self.entries.iter().filter(|entry| entry.key != 0) {
+            table.store(entry.key - 1, entry.context, entry.proof, entry.disproof);
+        }
+        *self = table;
+    }
+
+    //The low bits of the keys only describe the first columns, so they have to be mixed. The
+    //keys use 49 bits, which leaves room for the context, so that the searches of the same
+    //position with different goals do not fight for the same entries. The entries go by
+    //pairs, a position may be in either entry of its pair.
+    fn bucket(&self, key: u64, context: u8) -> usize {
+        let buckets = self.capacity / 2;
+        ((key << 8 | context as u64).wrapping_mul(0x9E3779B97F4A7C15) >> 32) as usize % buckets * 2
+    }
+
+    pub(crate) fn store(&mut self, key: u64, context: u8, proof: u32, disproof: u32) {
+        if self.entries.is_empty() {
+            self.entries = vec![Entry::default(); self.capacity];
+        }
+        let bucket = self.bucket(key, context);
+        let new_entry = Entry {
+            key: key + 1,
+            proof,
+            disproof,
+            context
+        };
+
+        //The position itself keeps its entry
+        let pair = &mut self.entries[bucket..bucket + 2];
+        if let Some(entry) = pair.iter_mut().find(|entry| entry.key == key + 1 && entry.context == context) {
+            *entry = new_entry;
+            return;
+        }
+
+        //The first entry holds the newest position. The victim is an empty entry, then with
+        //KeepSolved an unsolved one, then the oldest one.
+        let keep_solved = self.replacement == Replacement::KeepSolved;
+        let victim = if pair[0].key == 0 {
+            0
+        } else if pair[1].key == 0 {
+            1
+        } else if keep_solved && !pair[0].is_solved() && pair[1].is_solved() {
+            0
+        } else {
+            1
+        };
+        if pair[victim].key == 0 {
+            self.used += 1;
+        }
+        if victim == 1 {
+            pair[1] = pair[0];
+        }
+        pair[0] = new_entry;
+    }
+
+    //Does not count in the statistics
+    pub(crate) fn get(&self, key: u64, context: u8) -> Option<(u32, u32)> {
+        let bucket = self.bucket(key, context);
+        let pair = self.entries.get(bucket..bucket + 2)?;
+        pair.iter()
+            .find(|entry| entry.key == key + 1 && entry.context == context)
+            .map(|entry| (entry.proof, entry.disproof))
+    }
+
+    pub(crate) fn lookup(&mut self, key: u64, context: u8) -> Option<(u32, u32)> {
+        self.lookups += 1;
+        let numbers = self.get(key, context);
+        if numbers.is_some() {
+            self.hits += 1;
+        }
+        numbers
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MEMORY, Replacement::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A single pair of entries, so that every position falls on it
+    fn small_table(replacement: Replacement) -> TranspositionTable {
+        TranspositionTable::new(0, replacement)
+    }
+
+    #[test]
+    fn always_replaces_the_oldest() {
+        let mut table = small_table(Replacement::Always);
+        table.store(1, 0, 0, 5);
+        table.store(2, 0, 3, 3);
+        table.store(3, 0, 4, 4);
+        assert_eq!(table.get(1, 0), None);
+        assert_eq!(table.get(2, 0), Some((3, 3)));
+        assert_eq!(table.get(3, 0), Some((4, 4)));
+        assert_eq!(table.stats().used(), 2);
+    }
+
+    #[test]
+    fn keep_solved_replaces_the_unsolved_entry() {
+        //The solved position is the oldest one, in the second entry
+        let mut table = small_table(Replacement::KeepSolved);
+        table.store(1, 0, 0, 5);
+        table.store(2, 0, 3, 3);
+        table.store(3, 0, 4, 4);
+        table.store(4, 0, 6, 6);
+        assert_eq!(table.get(1, 0), Some((0, 5)));
+        assert_eq!(table.get(4, 0), Some((6, 6)));
+
+        //The solved position is the newest one, in the first entry
+        let mut table = small_table(Replacement::KeepSolved);
+        table.store(2, 0, 3, 3);
+        table.store(1, 0, 5, 0);
+        table.store(3, 0, 4, 4);
+        assert_eq!(table.get(1, 0), Some((5, 0)));
+        assert_eq!(table.get(2, 0), None);
+
+        //Two solved positions: the oldest one goes
+        table.store(5, 0, 0, 1);
+        table.store(6, 0, 0, 2);
+        assert_eq!(table.get(5, 0), Some((0, 1)));
+        assert_eq!(table.get(6, 0), Some((0, 2)));
+        assert_eq!(table.get(1, 0), None);
+    }
+
+    #[test]
+    fn contexts_are_different_positions() {
+        let mut table = TranspositionTable::new(1 << 16, Replacement::Always);
+        table.store(42, 0, 1, 2);
+        table.store(42, 1, 3, 4);
+        assert_eq!(table.get(42, 0), Some((1, 2)));
+        assert_eq!(table.get(42, 1), Some((3, 4)));
+        assert_eq!(table.get(43, 0), None);
+    }
+
+    #[test]
+    fn resize_keeps_the_positions() {
+        let mut table = TranspositionTable::new(1 << 16, Replacement::Always);
+        for key in 0..100 {
+            table.store(key, 0, key as u32 + 1, 1);
+        }
+        table.resize(1 << 20);
+        assert!((0..100).all(|key| table.get(key, 0) == Some((key as u32 + 1, 1))));
+    }
+}